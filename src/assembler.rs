@@ -0,0 +1,488 @@
+use crate::chip_eight::PROGRAM_START_ADDR;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+//Assembles classic Chip8 mnemonics (the same syntax the disassembler prints) into a ROM starting at 0x200.
+//
+//  ; comments run to the end of the line
+//  speed EQU 4              constants
+//  loop:                    labels, optionally followed by an instruction
+//      LD V0, speed
+//      DRW V0, V1, 5
+//      JP loop
+//  ball:
+//      DB "..XX....", 0x18  sprite literals are a quoted row of 8 pixels; X or # is set, anything else is clear
+//      DW 0x1234            16 bit words, big endian
+//
+//Numbers can be decimal, 0x1F / #1F / $1F hex or 0b0101 / %0101 binary.  Operands can add or subtract terms: label + 2
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize, //1 based source line
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssembleError {}
+
+enum Symbol {
+    Address(usize),
+    Constant(String), //Expression, evaluated when used so constants can refer to labels defined later
+}
+
+struct Statement<'a> {
+    line: usize,
+    mnemonic: String,
+    operands: Vec<&'a str>,
+}
+
+const MAX_SYMBOL_DEPTH: usize = 32; //Stops constants that refer to each other from recursing forever
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    //Pass 1: find labels and constants and work out where every statement will be placed
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut addr = PROGRAM_START_ADDR;
+
+    for (index, raw_line) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = strip_comment(raw_line).trim();
+
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if is_identifier(label) {
+                define(&mut symbols, label, Symbol::Address(addr), line)?;
+                text = text[colon + 1..].trim();
+            }
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, rest) = split_first_word(text);
+        let (second, value) = split_first_word(rest);
+        if second.eq_ignore_ascii_case("EQU") {
+            if !is_identifier(mnemonic) {
+                return Err(error(
+                    line,
+                    format!("'{}' is not a valid constant name", mnemonic),
+                ));
+            }
+            define(
+                &mut symbols,
+                mnemonic,
+                Symbol::Constant(value.to_string()),
+                line,
+            )?;
+            continue;
+        }
+
+        let statement = Statement {
+            line,
+            mnemonic: mnemonic.to_ascii_uppercase(),
+            operands: split_operands(rest),
+        };
+        addr += statement_size(&statement)?;
+        statements.push(statement);
+    }
+
+    //Pass 2: encode now that every label is known
+    let mut rom = Vec::new();
+    for statement in statements.iter() {
+        encode(statement, &symbols, &mut rom)?;
+    }
+    Ok(rom)
+}
+
+fn error(line: usize, message: String) -> AssembleError {
+    AssembleError { line, message }
+}
+
+fn define(
+    symbols: &mut HashMap<String, Symbol>,
+    name: &str,
+    symbol: Symbol,
+    line: usize,
+) -> Result<(), AssembleError> {
+    if symbols.insert(name.to_string(), symbol).is_some() {
+        return Err(error(line, format!("'{}' is defined more than once", name)));
+    }
+    Ok(())
+}
+
+//Comments start with ; unless it is inside a quoted sprite literal
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_first_word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn split_operands(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    text.split(',').map(str::trim).collect()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn statement_size(statement: &Statement) -> Result<usize, AssembleError> {
+    Ok(match statement.mnemonic.as_str() {
+        "DB" => statement
+            .operands
+            .iter()
+            .map(|operand| sprite_literal(operand).map_or(1, |row| row.len()))
+            .sum(),
+        "DW" => 2 * statement.operands.len(),
+        "LD" if statement.operands.len() == 2 && long_operand(statement.operands[1]).is_some() => 4,
+        _ => 2,
+    })
+}
+
+//"LONG expr" operand of the XO-CHIP F000 NNNN instruction
+fn long_operand(operand: &str) -> Option<&str> {
+    let (word, rest) = split_first_word(operand);
+    if word.eq_ignore_ascii_case("LONG") {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+//"..XX...." or "..XX....XX......" becomes one or two bytes of pixels
+fn sprite_literal(operand: &str) -> Option<Vec<u8>> {
+    let pixels = operand.strip_prefix('"')?.strip_suffix('"')?;
+    let bits: Vec<bool> = pixels
+        .chars()
+        .map(|c| c == 'X' || c == 'x' || c == '#')
+        .collect();
+    if bits.len() != 8 && bits.len() != 16 {
+        return None;
+    }
+    Some(
+        bits.chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .fold(0u8, |value, &set| (value << 1) | set as u8)
+            })
+            .collect(),
+    )
+}
+
+fn register(operand: &str) -> Option<u16> {
+    let digits = operand
+        .strip_prefix('V')
+        .or_else(|| operand.strip_prefix('v'))?;
+    if digits.len() != 1 {
+        return None;
+    }
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn number(text: &str) -> Option<i64> {
+    let (digits, radix) = if let Some(hex) = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('#'))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        (hex, 16)
+    } else if let Some(binary) = text
+        .strip_prefix("0b")
+        .or_else(|| text.strip_prefix("0B"))
+        .or_else(|| text.strip_prefix('%'))
+    {
+        (binary, 2)
+    } else {
+        (text, 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+//Sum of numbers, labels and constants joined by + and -
+fn evaluate(
+    expression: &str,
+    symbols: &HashMap<String, Symbol>,
+    line: usize,
+    depth: usize,
+) -> Result<i64, AssembleError> {
+    if depth > MAX_SYMBOL_DEPTH {
+        return Err(error(
+            line,
+            "constants refer to each other in a loop".to_string(),
+        ));
+    }
+
+    let mut total: i64 = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    //A trailing + flushes the last term
+    for c in expression.chars().chain(std::iter::once('+')) {
+        match c {
+            '+' | '-' => {
+                let text = term.trim();
+                if text.is_empty() {
+                    if c == '-' {
+                        sign = -sign; //unary minus
+                        continue;
+                    }
+                    return Err(error(
+                        line,
+                        format!("malformed expression '{}'", expression),
+                    ));
+                }
+                let value = match number(text) {
+                    Some(value) => value,
+                    None if !is_identifier(text) => {
+                        return Err(error(line, format!("'{}' is not a valid number", text)))
+                    }
+                    None => match symbols.get(text) {
+                        Some(Symbol::Address(addr)) => *addr as i64,
+                        Some(Symbol::Constant(value)) => evaluate(value, symbols, line, depth + 1)?,
+                        None => return Err(error(line, format!("unknown symbol '{}'", text))),
+                    },
+                };
+                total = value
+                    .checked_mul(sign)
+                    .and_then(|value| total.checked_add(value))
+                    .ok_or_else(|| error(line, format!("'{}' is too large", expression)))?;
+                sign = if c == '-' { -1 } else { 1 };
+                term.clear();
+            }
+            _ => term.push(c),
+        }
+    }
+    Ok(total)
+}
+
+fn encode(
+    statement: &Statement,
+    symbols: &HashMap<String, Symbol>,
+    rom: &mut Vec<u8>,
+) -> Result<(), AssembleError> {
+    let line = statement.line;
+    let operands = &statement.operands;
+    let mnemonic = statement.mnemonic.as_str();
+
+    //Evaluates an operand and checks it fits in max.  Negative bytes are stored as two's complement
+    let value = |operand: &str, max: i64| -> Result<u16, AssembleError> {
+        let value = evaluate(operand, symbols, line, 0)?;
+        if value > max || value < -(max + 1) / 2 {
+            return Err(error(
+                line,
+                format!("{} doesn't fit in {:#X}", operand, max),
+            ));
+        }
+        Ok((value & max) as u16)
+    };
+    let vx = |operand: &str| {
+        register(operand)
+            .ok_or_else(|| error(line, format!("expected a register, found '{}'", operand)))
+    };
+    let is = |operand: &str, name: &str| operand.eq_ignore_ascii_case(name);
+
+    let opcode = match (mnemonic, operands.as_slice()) {
+        ("DB", values) => {
+            for operand in values.iter() {
+                match sprite_literal(operand) {
+                    Some(row) => rom.extend_from_slice(&row),
+                    None => rom.push(value(operand, 0xFF)? as u8),
+                }
+            }
+            return Ok(());
+        }
+        ("DW", values) => {
+            for operand in values.iter() {
+                rom.extend_from_slice(&value(operand, 0xFFFF)?.to_be_bytes());
+            }
+            return Ok(());
+        }
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SCD", [n]) => 0x00C0 | value(n, 0xF)?,
+        ("SCU", [n]) => 0x00D0 | value(n, 0xF)?,
+        ("SCR", []) => 0x00FB,
+        ("SCL", []) => 0x00FC,
+        ("EXIT", []) => 0x00FD,
+        ("LOW", []) => 0x00FE,
+        ("HIGH", []) => 0x00FF,
+        ("JP", [v0, addr]) if is(v0, "V0") => 0xB000 | value(addr, 0xFFF)?,
+        ("JP", [addr]) => 0x1000 | value(addr, 0xFFF)?,
+        ("CALL", [addr]) => 0x2000 | value(addr, 0xFFF)?,
+        ("SE", [x, y]) if register(y).is_some() => 0x5000 | vx(x)? << 8 | vx(y)? << 4,
+        ("SE", [x, k]) => 0x3000 | vx(x)? << 8 | value(k, 0xFF)?,
+        ("SNE", [x, y]) if register(y).is_some() => 0x9000 | vx(x)? << 8 | vx(y)? << 4,
+        ("SNE", [x, k]) => 0x4000 | vx(x)? << 8 | value(k, 0xFF)?,
+        ("SAVE", [x, y]) => 0x5002 | vx(x)? << 8 | vx(y)? << 4,
+        ("LOAD", [x, y]) => 0x5003 | vx(x)? << 8 | vx(y)? << 4,
+        ("LD", [i, addr]) if is(i, "I") => match long_operand(addr) {
+            Some(addr) => {
+                rom.extend_from_slice(&[0xF0, 0x00]);
+                value(addr, 0xFFFF)?
+            }
+            None => 0xA000 | value(addr, 0xFFF)?,
+        },
+        ("LD", [x, dt]) if is(dt, "DT") => 0xF007 | vx(x)? << 8,
+        ("LD", [x, k]) if is(k, "K") => 0xF00A | vx(x)? << 8,
+        ("LD", [dt, x]) if is(dt, "DT") => 0xF015 | vx(x)? << 8,
+        ("LD", [st, x]) if is(st, "ST") => 0xF018 | vx(x)? << 8,
+        ("LD", [f, x]) if is(f, "F") => 0xF029 | vx(x)? << 8,
+        ("LD", [hf, x]) if is(hf, "HF") => 0xF030 | vx(x)? << 8,
+        ("LD", [b, x]) if is(b, "B") => 0xF033 | vx(x)? << 8,
+        ("LD", [i, x]) if is(i, "[I]") => 0xF055 | vx(x)? << 8,
+        ("LD", [x, i]) if is(i, "[I]") => 0xF065 | vx(x)? << 8,
+        ("LD", [r, x]) if is(r, "R") => 0xF075 | vx(x)? << 8,
+        ("LD", [x, r]) if is(r, "R") => 0xF085 | vx(x)? << 8,
+        ("LD", [x, y]) if register(y).is_some() => 0x8000 | vx(x)? << 8 | vx(y)? << 4,
+        ("LD", [x, k]) => 0x6000 | vx(x)? << 8 | value(k, 0xFF)?,
+        ("ADD", [i, x]) if is(i, "I") => 0xF01E | vx(x)? << 8,
+        ("ADD", [x, y]) if register(y).is_some() => 0x8004 | vx(x)? << 8 | vx(y)? << 4,
+        ("ADD", [x, k]) => 0x7000 | vx(x)? << 8 | value(k, 0xFF)?,
+        ("OR", [x, y]) => 0x8001 | vx(x)? << 8 | vx(y)? << 4,
+        ("AND", [x, y]) => 0x8002 | vx(x)? << 8 | vx(y)? << 4,
+        ("XOR", [x, y]) => 0x8003 | vx(x)? << 8 | vx(y)? << 4,
+        ("SUB", [x, y]) => 0x8005 | vx(x)? << 8 | vx(y)? << 4,
+        ("SHR", [x]) => 0x8006 | vx(x)? << 8 | vx(x)? << 4,
+        ("SHR", [x, y]) => 0x8006 | vx(x)? << 8 | vx(y)? << 4,
+        ("SUBN", [x, y]) => 0x8007 | vx(x)? << 8 | vx(y)? << 4,
+        ("SHL", [x]) => 0x800E | vx(x)? << 8 | vx(x)? << 4,
+        ("SHL", [x, y]) => 0x800E | vx(x)? << 8 | vx(y)? << 4,
+        ("RND", [x, k]) => 0xC000 | vx(x)? << 8 | value(k, 0xFF)?,
+        ("DRW", [x, y, n]) => 0xD000 | vx(x)? << 8 | vx(y)? << 4 | value(n, 0xF)?,
+        ("SKP", [x]) => 0xE09E | vx(x)? << 8,
+        ("SKNP", [x]) => 0xE0A1 | vx(x)? << 8,
+        ("PLANE", [n]) => 0xF001 | value(n, 0xF)? << 8,
+        ("AUDIO", []) => 0xF002,
+        ("PITCH", [x]) => 0xF03A | vx(x)? << 8,
+        _ => {
+            return Err(error(
+                line,
+                format!("unknown instruction '{} {}'", mnemonic, operands.join(", ")),
+            ))
+        }
+    };
+
+    rom.extend_from_slice(&opcode.to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{self, Syntax};
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|word| (word[0] as u16) << 8 | *word.get(1).unwrap_or(&0) as u16)
+            .collect()
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let source = "start:\n  CALL draw ; forward\n  JP start\ndraw: RET\n";
+        assert_eq!(
+            words(&assemble(source).unwrap()),
+            vec![0x2204, 0x1200, 0x00EE]
+        );
+    }
+
+    #[test]
+    fn constants_and_expressions() {
+        let source =
+            "speed EQU 4\nlast EQU end - 2\n LD V0, speed + 1\n LD I, last\n ADD V1, -1\nend:\n";
+        assert_eq!(
+            words(&assemble(source).unwrap()),
+            vec![0x6005, 0xA204, 0x71FF]
+        );
+        assert_eq!(
+            words(
+                &assemble("LD V0, 0x10\nLD V1, #1F\nLD V2, $0A\nLD V3, 0b101\nLD V4, %11").unwrap()
+            ),
+            vec![0x6010, 0x611F, 0x620A, 0x6305, 0x6403]
+        );
+    }
+
+    #[test]
+    fn sprite_and_data_literals() {
+        let source = "DB \"..XX....\", \"X......#\", 0x18 ; not a ; comment\nDW 0x1234\nDB \"XXXXXXXX........\"";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0x30, 0x81, 0x18, 0x12, 0x34, 0xFF, 0x00]
+        );
+    }
+
+    #[test]
+    fn long_load_is_four_bytes() {
+        let source = "LD I, LONG data\nEXIT\ndata: DB 1";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0x01]
+        );
+    }
+
+    #[test]
+    fn errors_report_the_line() {
+        let error = |source: &str| assemble(source).unwrap_err();
+        assert_eq!(error("CLS\n\nJP nowhere").line, 3);
+        assert_eq!(error("CLS\nFLY V0").message, "unknown instruction 'FLY V0'");
+        assert_eq!(error("a:\na: CLS").line, 2);
+        assert_eq!(error("LD V0, 256").message, "256 doesn't fit in 0xFF");
+        assert_eq!(error("LD VG, 1").message, "expected a register, found 'VG'");
+        assert_eq!(error("a EQU b\nb EQU a\nLD V0, a").line, 3);
+    }
+
+    #[test]
+    fn huge_numbers_are_errors_not_panics() {
+        let error = assemble("DW 9223372036854775807 + 1").unwrap_err();
+        assert_eq!(error.message, "'9223372036854775807 + 1' is too large");
+        assert!(assemble("DW -9223372036854775807 - 2").is_err());
+        let error = assemble("DW 99999999999999999999").unwrap_err();
+        assert_eq!(
+            error.message,
+            "'99999999999999999999' is not a valid number"
+        );
+    }
+
+    #[test]
+    fn disassembled_roms_reassemble_to_the_same_bytes() {
+        let rom: Vec<u8> = [
+            0x00E0u16, 0x6A02, 0x6B0C, 0xA21E, 0xDAB6, 0x3A3F, 0x1214, 0x7A01, 0x2218, 0x120A,
+            0xF000, 0x0300, 0x00FD, 0x8AB4, 0xE19E, 0xF155, 0x00EE,
+        ]
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .chain([0x3C, 0x42, 0x81, 0x81, 0x42, 0x3C, 0x07].iter().copied())
+        .collect();
+        let source = disassembler::source(&rom, Syntax::Classic);
+        assert_eq!(assemble(&source).unwrap(), rom);
+    }
+}
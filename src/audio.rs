@@ -0,0 +1,227 @@
+use crate::chip_eight::{ChipEight, AUDIO_PATTERN_SIZE, DEFAULT_AUDIO_PITCH};
+
+//Chip8 only has one sound: a tone that plays for as long as the sound timer is non-zero.
+//XO-CHIP replaces the tone with a 128 sample 1-bit pattern played back at a rate set by the pitch register.
+//Everything here is independent of SDL so a headless build can use NullAudio.
+
+const PATTERN_BITS: usize = AUDIO_PATTERN_SIZE * 8;
+
+//XO-CHIP playback rate in samples per second: 4000 * 2^((pitch - 64) / 48)
+pub fn pattern_playback_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+//Names accepted by Waveform::from_name
+pub const WAVEFORMS: [&str; 4] = ["square", "sine", "triangle", "sawtooth"];
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub frequency: f32, //Hz
+    pub volume: f32,    //0.0 - 1.0
+    pub waveform: Waveform,
+    pub muted: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+            muted: false,
+        }
+    }
+}
+
+pub trait AudioBackend {
+    //Called once per frame so the backend can start or stop the tone to match the sound timer
+    fn update(&mut self, chip8: &ChipEight);
+    fn toggle_mute(&mut self);
+}
+
+//Audio backend for headless runs or when no audio device is available.  Does nothing.
+#[derive(Default)]
+pub struct NullAudio;
+
+impl AudioBackend for NullAudio {
+    fn update(&mut self, _chip8: &ChipEight) {}
+    fn toggle_mute(&mut self) {}
+}
+
+//Generates the samples for the tone.  Audio device backends call fill from their audio callback.
+pub struct Tone {
+    pub settings: AudioSettings,
+    pub playing: bool,
+    pub pattern: Option<[u8; AUDIO_PATTERN_SIZE]>, //XO-CHIP pattern; replaces the waveform when set
+    pub pattern_rate: f32,                         //XO-CHIP pattern samples per second
+    sample_rate: f32,
+    phase: f32, //Position within the current period or pattern; 0.0 - 1.0
+}
+
+impl Tone {
+    pub fn new(settings: AudioSettings, sample_rate: i32) -> Self {
+        Tone {
+            settings,
+            playing: false,
+            pattern: None,
+            pattern_rate: pattern_playback_rate(DEFAULT_AUDIO_PITCH),
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+        }
+    }
+
+    //Copies the XO-CHIP pattern and pitch from the interpreter
+    pub fn sync_pattern(&mut self, chip8: &ChipEight) {
+        self.pattern = chip8.audio_pattern().copied();
+        self.pattern_rate = pattern_playback_rate(chip8.audio_pitch());
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        if !self.playing || self.settings.muted {
+            for sample in out.iter_mut() {
+                *sample = 0.0;
+            }
+            self.phase = 0.0; //Start the next beep at the beginning of a period so it doesn't click
+            return;
+        }
+
+        if let Some(pattern) = self.pattern {
+            //The phase covers the whole pattern so it loops once every PATTERN_BITS samples
+            let phase_step = self.pattern_rate / PATTERN_BITS as f32 / self.sample_rate;
+            for sample in out.iter_mut() {
+                let bit = (self.phase * PATTERN_BITS as f32) as usize % PATTERN_BITS;
+                let amplitude = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                };
+                *sample = amplitude * self.settings.volume;
+                self.phase = (self.phase + phase_step) % 1.0;
+            }
+            return;
+        }
+
+        let phase_step = self.settings.frequency / self.sample_rate;
+        for sample in out.iter_mut() {
+            let amplitude = match self.settings.waveform {
+                Waveform::Square => {
+                    if self.phase < 0.5 {
+                        1.0
+                    } else {
+                        -1.0
+                    }
+                }
+                Waveform::Sine => (self.phase * 2.0 * std::f32::consts::PI).sin(),
+                Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+                Waveform::Sawtooth => 2.0 * self.phase - 1.0,
+            };
+            *sample = amplitude * self.settings.volume;
+            self.phase = (self.phase + phase_step) % 1.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Four samples per period so each waveform can be checked at 0, 1/4, 1/2 and 3/4 of the way through
+    fn tone(waveform: Waveform) -> Tone {
+        let settings = AudioSettings {
+            frequency: 2.0,
+            volume: 0.5,
+            waveform,
+            muted: false,
+        };
+        let mut tone = Tone::new(settings, 8);
+        tone.playing = true;
+        tone
+    }
+
+    fn samples(tone: &mut Tone, count: usize) -> Vec<f32> {
+        let mut out = vec![f32::NAN; count];
+        tone.fill(&mut out);
+        //Round away floating point noise from the sine
+        out.iter()
+            .map(|sample| (sample * 1000.0).round() / 1000.0)
+            .collect()
+    }
+
+    #[test]
+    fn waveforms_are_scaled_by_the_volume() {
+        assert_eq!(
+            samples(&mut tone(Waveform::Square), 8),
+            [0.5, 0.5, -0.5, -0.5, 0.5, 0.5, -0.5, -0.5]
+        );
+        assert_eq!(samples(&mut tone(Waveform::Sine), 4), [0.0, 0.5, 0.0, -0.5]);
+        assert_eq!(
+            samples(&mut tone(Waveform::Triangle), 4),
+            [-0.5, 0.0, 0.5, 0.0]
+        );
+        assert_eq!(
+            samples(&mut tone(Waveform::Sawtooth), 4),
+            [-0.5, -0.25, 0.0, 0.25]
+        );
+    }
+
+    #[test]
+    fn muted_or_stopped_tones_are_silent() {
+        let mut muted = tone(Waveform::Square);
+        muted.settings.muted = true;
+        assert_eq!(samples(&mut muted, 4), [0.0; 4]);
+
+        let mut stopped = tone(Waveform::Square);
+        stopped.playing = false;
+        assert_eq!(samples(&mut stopped, 4), [0.0; 4]);
+    }
+
+    #[test]
+    fn stopping_restarts_the_next_beep_at_the_start_of_a_period() {
+        let mut tone = tone(Waveform::Sawtooth);
+        samples(&mut tone, 3);
+        tone.playing = false;
+        samples(&mut tone, 1);
+        tone.playing = true;
+        assert_eq!(samples(&mut tone, 2), [-0.5, -0.25]);
+    }
+
+    #[test]
+    fn patterns_play_most_significant_bit_first() {
+        let mut tone = tone(Waveform::Sine);
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern[0] = 0b1011_0000;
+        tone.pattern = Some(pattern);
+        //One bit per sample
+        tone.pattern_rate = 8.0;
+        assert_eq!(samples(&mut tone, 6), [0.5, -0.5, 0.5, 0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn pitch_64_plays_patterns_at_4000_hz() {
+        assert_eq!(pattern_playback_rate(64), 4000.0);
+        assert_eq!(pattern_playback_rate(DEFAULT_AUDIO_PITCH), 4000.0);
+        //Every 48 steps is an octave
+        assert!((pattern_playback_rate(112) - 8000.0).abs() < 0.01);
+        assert!((pattern_playback_rate(16) - 2000.0).abs() < 0.01);
+    }
+}
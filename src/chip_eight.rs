@@ -1,11 +1,31 @@
-use num_derive::FromPrimitive;
-
+use crate::error::ChipError;
+use crate::quirks::Quirks;
+use crate::random::Random;
+use crate::save_state::*;
 use std::fs::File;
 use std::io::Read;
+use std::mem;
+use std::ops::Range;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const DISPLAY_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+pub const HIRES_DISPLAY_WIDTH: usize = 128; //SUPER-CHIP high resolution mode
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+
+pub const TIMER_FREQUENCY: u32 = 60; //Hz
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10; //600 instructions per second at 60 frames per second
 
-//use rand::prelude::*;
+pub const MEMORY_SIZE: usize = 0x10000; //XO-CHIP extends the original 4k to 64k
+pub const PROGRAM_START_ADDR: usize = 0x200;
+pub const RPL_FLAG_COUNT: usize = 16;
+pub const AUDIO_PATTERN_SIZE: usize = 16; //bytes; 128 1-bit samples
+pub const DEFAULT_AUDIO_PITCH: u8 = 64; //4000 samples per second
 
 const SPRITE_START_ADDR: usize = 0x50;
+const BIG_SPRITE_START_ADDR: usize = 0xA0; //SUPER-CHIP 8x10 font follows the 4x5 font
+const PLANE_MASK: u8 = 0b11; //XO-CHIP has two bitplanes.  Each display byte holds one bit per plane
+const SCROLL_HORIZONTAL_PIXELS: isize = 4; //00FB/00FC always scroll by 4 pixels
 const SUB_OPCODE_MASK: u16 = 0x000F;
 const SUB_OPCODE_MASK2: u16 = 0x00FF;
 const OPCODE_MASK: u16 = 0xF000;
@@ -13,13 +33,15 @@ const ADDR_MASK: u16 = 0x0FFF;
 const BYTE_MASK: u16 = 0x00FF;
 const NIBBLE_MASK: u16 = 0x000F;
 
-enum Opcodes {
+pub(crate) enum Opcodes {
     ClearOrReturn(u16),            //sub code
     Jump(usize),                   //address
     Call(usize),                   //address
     SkipEqual(usize, u8),          //Vx, K
     SkipNotEqual(usize, u8),       //Vx, K
     SkipEqualVy(usize, usize),     //Vx, Vy
+    SaveRange(usize, usize),       //Vx, Vy
+    LoadRange(usize, usize),       //Vx, Vy
     LoadVxK(usize, u8),            //Vx, K
     AddByte(usize, u8),            //Vx, K
     Arithmetic(u16, usize, usize), //sub code, Vx, Vy
@@ -27,19 +49,74 @@ enum Opcodes {
     LoadI(usize),                  //address,
     JumpOffset(usize),             //address,
     RandomVxByte(usize, u8),       //Vx, K,
-    Draw(u16, u16, u16),           //Vx, Vy, Height
+    Draw(usize, usize, u16),       //Vx, Vy, Height
     SkipPressed(usize, u16),       //Vx, sub code
-    Misc(usize),                   //Vx
+    Misc(usize, u16),              //Vx, sub code
     BadOpcode,
 }
 
+//What happened on a successful emulation cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    WaitingForKey, //FX0A with no key pressed.  The instruction repeats next cycle
+    Exited,        //The ROM has executed 00FD
+}
+
+impl Opcodes {
+    //Splits an opcode into its instruction and operands.  Doesn't touch the machine so tools can decode ROMs too
+    pub(crate) fn decode(opcode: u16) -> Self {
+        match opcode & OPCODE_MASK {
+            0x0000 => Opcodes::ClearOrReturn(opcode & SUB_OPCODE_MASK2),
+            0x1000 => Opcodes::Jump((opcode & ADDR_MASK) as usize),
+            0x2000 => Opcodes::Call((opcode & ADDR_MASK) as usize),
+            0x3000 => Opcodes::SkipEqual(ChipEight::vx_mask(opcode), (opcode & BYTE_MASK) as u8),
+            0x4000 => {
+                Opcodes::SkipNotEqual(ChipEight::vx_mask(opcode), (opcode & BYTE_MASK) as u8)
+                // I want to punch visual studio code / rust for auto formatting this line.  Monitors aren't square anymore!!!
+            }
+            0x5000 => {
+                let (vx, vy) = (ChipEight::vx_mask(opcode), ChipEight::vy_mask(opcode));
+                match opcode & SUB_OPCODE_MASK {
+                    0x0 => Opcodes::SkipEqualVy(vx, vy),
+                    0x2 => Opcodes::SaveRange(vx, vy),
+                    0x3 => Opcodes::LoadRange(vx, vy),
+                    _ => Opcodes::BadOpcode,
+                }
+            }
+            0x6000 => Opcodes::LoadVxK(ChipEight::vx_mask(opcode), (opcode & BYTE_MASK) as u8),
+            0x7000 => Opcodes::AddByte(ChipEight::vx_mask(opcode), (opcode & BYTE_MASK) as u8),
+            0x8000 => Opcodes::Arithmetic(
+                opcode & SUB_OPCODE_MASK,
+                ChipEight::vx_mask(opcode),
+                ChipEight::vy_mask(opcode),
+            ),
+            0x9000 => {
+                Opcodes::SkipNotEqualVy(ChipEight::vx_mask(opcode), ChipEight::vy_mask(opcode))
+            }
+            0xA000 => Opcodes::LoadI((opcode & ADDR_MASK) as usize),
+            0xB000 => Opcodes::JumpOffset((opcode & ADDR_MASK) as usize),
+            0xC000 => Opcodes::RandomVxByte(ChipEight::vx_mask(opcode), (opcode & BYTE_MASK) as u8),
+            0xD000 => Opcodes::Draw(
+                ChipEight::vx_mask(opcode),
+                ChipEight::vy_mask(opcode),
+                opcode & NIBBLE_MASK,
+            ),
+            0xE000 => Opcodes::SkipPressed(ChipEight::vx_mask(opcode), opcode & SUB_OPCODE_MASK),
+            0xF000 => Opcodes::Misc(ChipEight::vx_mask(opcode), opcode & SUB_OPCODE_MASK2),
+            _ => Opcodes::BadOpcode,
+        }
+    }
+}
+
 pub struct ChipEight {
     opcode: u16, //op code is two bytes long
     //memory map
     //0x000-0x1FF - Chip 8 interpreter (contains font set in emu)
     //0x050-0x0A0 - Used for the built in 4x5 pixel font set (0-F)
-    //0x200-0xFFF - Program ROM and work RAM
-    memory: [u8; 4096], //4k memmory
+    //0x0A0-0x140 - Used for the built in SUPER-CHIP 8x10 pixel font set (0-F)
+    //0x200-0xFFFF - Program ROM and work RAM.  Original Chip8 stops at 0xFFF, XO-CHIP extends it to 0xFFFF
+    memory: Vec<u8>, //64k memmory
 
     v_register: [u8; 16], //16 registers; v0 - vF
     i_register: usize,    //index register
@@ -48,33 +125,61 @@ pub struct ChipEight {
     delay_timer: u8,
     sound_timer: u8,
 
+    rpl_flags: [u8; RPL_FLAG_COUNT], //SUPER-CHIP HP-48 RPL user flags.  SUPER-CHIP uses 8, XO-CHIP 16
+    rpl_flags_dirty: bool,           //FX75 wrote the flags since the frontend last took them
+    rom_hash: u64,
+
+    audio_pattern: Option<[u8; AUDIO_PATTERN_SIZE]>, //XO-CHIP 1-bit sample loaded by F002.  None plays the plain tone
+    audio_pitch: u8,                                 //XO-CHIP playback rate register set by FX3A
+
     stack: [u16; 16], //The stack
     sp: usize,        //The stack pointer
 
     pub key: [bool; 16],
-    pub display: [u8; crate::DISPLAY_SIZE], //Chip8 has a display that is 64 x 32
+    pub display: Vec<u8>, //Chip8 has a display that is 64 x 32.  SUPER-CHIP can switch it to 128 x 64
+    display_dirty: bool,  //The display changed since the frontend last took it
+    hires: bool,
+    planes: u8,   //XO-CHIP bitplanes selected by FN01.  Bit 0 is plane 1, bit 1 is plane 2
+    exited: bool, //Set by the SUPER-CHIP 00FD exit instruction
+
+    quirks: Quirks, //Which interpretation of the ambiguous opcodes to use
+    seed: u64,      //The CXNN generator's starting seed, kept so a run can be reported and repeated
+    rng: Random,
 }
 
-#[derive(FromPrimitive)]
-enum SubOpcodeClearorReturn {
-    Clear = 0x0,
-    Return = 0xE,
+impl Default for ChipEight {
+    fn default() -> Self {
+        Self::new(Quirks::default())
+    }
 }
 
 impl ChipEight {
-    pub fn new() -> Self {
+    pub fn new(quirks: Quirks) -> Self {
+        let seed = rand::random(); //Normal play gets different numbers every run.  Use set_seed to repeat one
         let mut chip8 = Self {
             opcode: 0,
-            memory: [0; 4096],
+            memory: vec![0; MEMORY_SIZE],
             v_register: [0; 16],
             i_register: 0,
-            pc: 0x200, //program starts at 0x200
+            pc: PROGRAM_START_ADDR, //program starts at 0x200
             delay_timer: 0,
             sound_timer: 0,
+            rpl_flags: [0; RPL_FLAG_COUNT],
+            rpl_flags_dirty: false,
+            rom_hash: rom_hash(&[]),
+            audio_pattern: None,
+            audio_pitch: DEFAULT_AUDIO_PITCH,
             stack: [0; 16],
             sp: 0,
             key: [false; 16],
-            display: [0; crate::DISPLAY_SIZE],
+            display: vec![0; DISPLAY_SIZE],
+            display_dirty: true,
+            hires: false,
+            planes: 1,
+            exited: false,
+            quirks,
+            seed,
+            rng: Random::new(seed),
         };
 
         const FONT_SIZE: usize = 80;
@@ -97,141 +202,148 @@ impl ChipEight {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
 
+        //SUPER-CHIP large font.  SUPER-CHIP only defines 0-9; A-F are the XO-CHIP additions
+        const BIG_FONT_SIZE: usize = 160;
+        let big_font: [u8; BIG_FONT_SIZE] = [
+            0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+            0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+            0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+            0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+            0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+            0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+            0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+            0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+            0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+            0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
         //loading hardcoded fonts into memory
-        for x in 0..FONT_SIZE {
-            chip8.memory[SPRITE_START_ADDR + x] = font[x]
-        }
+        chip8.memory[SPRITE_START_ADDR..SPRITE_START_ADDR + FONT_SIZE].copy_from_slice(&font);
+        chip8.memory[BIG_SPRITE_START_ADDR..BIG_SPRITE_START_ADDR + BIG_FONT_SIZE]
+            .copy_from_slice(&big_font);
 
         chip8
     }
 
     //Returns index for V[X] from opcode
-    fn vx_mask(opcode: u16) -> usize {
+    pub(crate) fn vx_mask(opcode: u16) -> usize {
         const VX_MASK: u16 = 0x0F00;
         ((opcode & VX_MASK) >> 8) as usize
     }
 
     //Returns index for V[Y] from opcode
-    fn vy_mask(opcode: u16) -> usize {
+    pub(crate) fn vy_mask(opcode: u16) -> usize {
         const VY_MASK: u16 = 0x00F0;
         ((opcode & VY_MASK) >> 4) as usize
     }
 
-    fn fetch(&mut self) -> Opcodes {
-        let opcode: Opcodes;
+    fn fetch(&mut self) -> Result<Opcodes, ChipError> {
+        let bytes = &self.memory[Self::memory_range(self.pc, 2)?];
+        self.opcode = (bytes[0] as u16) << 8; //op code is two bytes long
+        self.opcode |= bytes[1] as u16;
 
-        self.opcode = (self.memory[self.pc] as u16) << 8; //op code is two bytes long
-        self.opcode |= (self.memory[self.pc + 1]) as u16;
+        Ok(Opcodes::decode(self.opcode))
+    }
 
-        match self.opcode & OPCODE_MASK {
-            0x0000 => opcode = Opcodes::ClearOrReturn(self.opcode & SUB_OPCODE_MASK),
-            0x1000 => opcode = Opcodes::Jump((self.opcode & ADDR_MASK) as usize),
-            0x2000 => opcode = Opcodes::Call((self.opcode & ADDR_MASK) as usize),
-            0x3000 => {
-                opcode =
-                    Opcodes::SkipEqual(Self::vx_mask(self.opcode), (self.opcode & BYTE_MASK) as u8)
-            }
-            0x4000 => {
-                opcode = Opcodes::SkipNotEqual(
-                    Self::vx_mask(self.opcode),
-                    (self.opcode & BYTE_MASK) as u8,
-                ) // I want to punch visual studio code / rust for auto formatting this line.  Monitors aren't square anymore!!!
-            }
-            0x5000 => {
-                opcode =
-                    Opcodes::SkipEqualVy(Self::vx_mask(self.opcode), Self::vy_mask(self.opcode))
-            }
-            0x6000 => {
-                opcode =
-                    Opcodes::LoadVxK(Self::vx_mask(self.opcode), (self.opcode & BYTE_MASK) as u8)
-            }
-            0x7000 => {
-                opcode =
-                    Opcodes::AddByte(Self::vx_mask(self.opcode), (self.opcode & BYTE_MASK) as u8)
-            }
-            0x8000 => {
-                opcode = Opcodes::Arithmetic(
-                    self.opcode & SUB_OPCODE_MASK,
-                    Self::vx_mask(self.opcode),
-                    Self::vy_mask(self.opcode),
-                )
-            }
-            0x9000 => {
-                opcode =
-                    Opcodes::SkipNotEqualVy(Self::vx_mask(self.opcode), Self::vy_mask(self.opcode))
-            }
-            0xA000 => opcode = Opcodes::LoadI((self.opcode & ADDR_MASK) as usize),
-            0xB000 => opcode = Opcodes::JumpOffset((self.opcode & ADDR_MASK) as usize),
-            0xC000 => {
-                opcode = Opcodes::RandomVxByte(
-                    Self::vx_mask(self.opcode),
-                    (self.opcode & BYTE_MASK) as u8,
-                )
-            }
-            0xD000 => {
-                opcode = Opcodes::Draw(
-                    self.v_register[Self::vx_mask(self.opcode)] as u16,
-                    self.v_register[Self::vy_mask(self.opcode)] as u16,
-                    self.opcode & NIBBLE_MASK,
-                )
-            }
-            0xE000 => {
-                opcode =
-                    Opcodes::SkipPressed(Self::vx_mask(self.opcode), self.opcode & SUB_OPCODE_MASK)
-            }
-            0xF000 => opcode = Opcodes::Misc(Self::vx_mask(self.opcode)),
-            _ => opcode = Opcodes::BadOpcode,
+    pub fn emulation_cycle(&mut self) -> Result<StepOutcome, ChipError> {
+        //After 00FD the interpreter is halted
+        if self.exited {
+            return Ok(StepOutcome::Exited);
         }
 
-        opcode
-    }
-
-    pub fn emulation_cycle(&mut self) {
         //Fetch opcode
-        let opcode = self.fetch();
+        let instruction_addr = self.pc;
+        let opcode = self.fetch()?;
         self.pc += 2; //increment the pc for the next instruction
+        let invalid_opcode = ChipError::InvalidOpcode(self.opcode, instruction_addr);
 
         //Decode opcode
         match opcode {
             Opcodes::ClearOrReturn(sub) => {
                 match sub {
                     //Clear Display
-                    0x0000 => {
-                        for i in 0..crate::DISPLAY_SIZE {
-                            self.display[i] = 0;
+                    0x00E0 => {
+                        //XO-CHIP only clears the selected planes
+                        for pixel in self.display.iter_mut() {
+                            *pixel &= !self.planes;
                         }
+                        self.display_dirty = true;
                     }
                     //00EE: Return from subroutine
-                    0x000E => {
+                    0x00EE => {
+                        if self.sp == 0 {
+                            return Err(ChipError::StackUnderflow);
+                        }
                         self.sp -= 1;
                         self.pc = self.stack[self.sp] as usize; //pop program counter off of the stack
                     }
-                    _ => {
-                        println!("----- other");
+                    //00CN: SUPER-CHIP scroll the display down N pixels
+                    0x00C0..=0x00CF => self.scroll(0, (sub & SUB_OPCODE_MASK) as isize),
+                    //00DN: XO-CHIP scroll the display up N pixels
+                    0x00D0..=0x00DF => self.scroll(0, -((sub & SUB_OPCODE_MASK) as isize)),
+                    //00FB: SUPER-CHIP scroll the display right 4 pixels
+                    0x00FB => self.scroll(SCROLL_HORIZONTAL_PIXELS, 0),
+                    //00FC: SUPER-CHIP scroll the display left 4 pixels
+                    0x00FC => self.scroll(-SCROLL_HORIZONTAL_PIXELS, 0),
+                    //00FD: SUPER-CHIP exit the interpreter
+                    0x00FD => {
+                        self.exited = true;
+                        return Ok(StepOutcome::Exited);
                     }
+                    //00FE: SUPER-CHIP switch to low resolution
+                    0x00FE => self.set_hires(false),
+                    //00FF: SUPER-CHIP switch to high resolution
+                    0x00FF => self.set_hires(true),
+                    //0NNN: Call machine code routine.  Not supported
+                    _ => return Err(invalid_opcode),
                 }
             }
             Opcodes::Jump(addr) => {
                 self.pc = addr; //jump to the address
             }
             Opcodes::Call(addr) => {
+                if self.sp >= self.stack.len() {
+                    return Err(ChipError::StackOverflow);
+                }
                 self.stack[self.sp] = self.pc as u16; //Push the program counter onto the stack
                 self.sp += 1;
                 self.pc = addr; //jump to the address
             }
             Opcodes::SkipEqual(vx, k) => {
                 if self.v_register[vx] == k {
-                    self.pc += 2;
+                    self.skip_next_instruction();
                 }
             }
             Opcodes::SkipNotEqual(vx, k) => {
                 if self.v_register[vx] != k {
-                    self.pc += 2;
+                    self.skip_next_instruction();
                 }
             }
             Opcodes::SkipEqualVy(vx, vy) => {
                 if self.v_register[vx] == self.v_register[vy] {
-                    self.pc += 2;
+                    self.skip_next_instruction();
+                }
+            }
+            //5XY2: XO-CHIP stores VX to VY in memory starting at I.  I is not changed
+            Opcodes::SaveRange(vx, vy) => {
+                let count = vx.max(vy) - vx.min(vy) + 1;
+                let memory = &mut self.memory[Self::memory_range(self.i_register, count)?];
+                for (offset, register) in Self::register_range(vx, vy).enumerate() {
+                    memory[offset] = self.v_register[register];
+                }
+            }
+            //5XY3: XO-CHIP loads VX to VY from memory starting at I.  I is not changed
+            Opcodes::LoadRange(vx, vy) => {
+                let count = vx.max(vy) - vx.min(vy) + 1;
+                let memory = &self.memory[Self::memory_range(self.i_register, count)?];
+                for (offset, register) in Self::register_range(vx, vy).enumerate() {
+                    self.v_register[register] = memory[offset];
                 }
             }
             Opcodes::LoadVxK(vx, k) => {
@@ -258,109 +370,175 @@ impl ChipEight {
                     0x0003 => {
                         self.v_register[vx] ^= self.v_register[vy];
                     }
-                    //Add Vy to Vx.  If sum is greater than 255 mark v[F] as 1
+                    //Add Vy to Vx.  If sum is greater than 255 mark v[F] as 1.
+                    //VF is written after the result so the flag wins when VF is the destination
                     0x0004 => {
-                        if self.v_register[vx] > 0xFF - self.v_register[Self::vx_mask(self.opcode)]
-                        {
-                            self.v_register[0xF] = 1;
-                        } else {
-                            self.v_register[0xF] = 0;
-                        }
-                        self.v_register[vx] = self.v_register[vx].wrapping_add(self.v_register[vy]);
+                        let (sum, carry) = self.v_register[vx].overflowing_add(self.v_register[vy]);
+                        self.v_register[vx] = sum;
+                        self.v_register[0xF] = carry as u8;
                     }
-                    //Sub
+                    //Sub Vy from Vx.  v[F] is 1 unless the subtraction borrows
                     0x0005 => {
-                        if self.v_register[vx] > self.v_register[vy] {
-                            self.v_register[0xF] = 1;
-                        } else {
-                            self.v_register[0xF] = 0;
-                        }
-                        self.v_register[vx] = self.v_register[vx].wrapping_sub(self.v_register[vy]);
+                        let (difference, borrow) =
+                            self.v_register[vx].overflowing_sub(self.v_register[vy]);
+                        self.v_register[vx] = difference;
+                        self.v_register[0xF] = !borrow as u8;
                     }
-                    //Right Shift
+                    //Right Shift.  v[F] is the bit shifted out
                     0x0006 => {
-                        self.v_register[0xF] = self.v_register[vx] & 0x01;
-                        self.v_register[vx] >>= 1;
+                        let value = if self.quirks.shift_uses_vy {
+                            self.v_register[vy]
+                        } else {
+                            self.v_register[vx]
+                        };
+                        self.v_register[vx] = value >> 1;
+                        self.v_register[0xF] = value & 0x01;
                     }
-                    //sub
+                    //Sub Vx from Vy and store it in Vx.  v[F] is 1 unless the subtraction borrows
                     0x0007 => {
-                        if self.v_register[vy] > self.v_register[vx] {
-                            self.v_register[0xF] = 1;
-                        } else {
-                            self.v_register[0xF] = 0;
-                        }
-                        self.v_register[vx] = self.v_register[vy] - self.v_register[vx];
+                        let (difference, borrow) =
+                            self.v_register[vy].overflowing_sub(self.v_register[vx]);
+                        self.v_register[vx] = difference;
+                        self.v_register[0xF] = !borrow as u8;
                     }
-                    //Left Shift
+                    //Left Shift.  v[F] is the bit shifted out
                     0x000E => {
-                        self.v_register[0xF] = self.v_register[vx] & 0x10;
-                        self.v_register[vx] <<= 1;
+                        let value = if self.quirks.shift_uses_vy {
+                            self.v_register[vy]
+                        } else {
+                            self.v_register[vx]
+                        };
+                        self.v_register[vx] = value << 1;
+                        self.v_register[0xF] = (value & 0x80) >> 7;
                     }
-                    _ => (),
+                    _ => return Err(invalid_opcode),
                 }
             }
             Opcodes::SkipNotEqualVy(vx, vy) => {
                 if self.v_register[vx] != self.v_register[vy] {
-                    self.pc += 2;
+                    self.skip_next_instruction();
                 }
             }
             //Sets I to the address NNN.
             Opcodes::LoadI(addr) => {
                 self.i_register = addr;
             }
-            //Jumps to the address NNN plus V0.
+            //Jumps to the address NNN plus V0.  With the jump_uses_vx quirk this is BXNN and jumps to XNN plus VX.
             Opcodes::JumpOffset(addr) => {
-                self.pc = addr + self.v_register[0] as usize;
+                let offset_register = if self.quirks.jump_uses_vx {
+                    Self::vx_mask(self.opcode)
+                } else {
+                    0
+                };
+                self.pc = addr + self.v_register[offset_register] as usize;
             }
             //Sets VX to the result of a bitwise and operation on a random number (Typically: 0 to 255) and NN.
             Opcodes::RandomVxByte(vx, k) => {
-                self.v_register[vx] = rand::random::<u8>() & k;
+                self.v_register[vx] = self.rng.next_u8() & k;
             }
-            //Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N+1 pixels.
+            //Draws a sprite at coordinate (VX, VY) that has a width of 8 pixels and a height of N pixels.
+            //SUPER-CHIP: When N is 0 the sprite is 16x16 and each row is two bytes.
             //Each row of 8 pixels is read as bit-coded starting from memory location I; I value doesn’t change after the execution of this instruction.
             //As described above, VF is set to 1 if any screen pixels are flipped from set to unset when the sprite is drawn, and to 0 if that doesn’t happen
             Opcodes::Draw(vx, vy, height) => {
+                let (vx, vy) = (self.v_register[vx], self.v_register[vy]);
                 self.v_register[0xF] = 0;
+                self.display_dirty = true;
+
+                //The starting coordinate always wraps.  The wrap_sprites quirk decides if the rest of the sprite wraps or clips
+                let width = self.display_width();
+                let height_limit = self.display_height();
+                let start_x = vx as usize % width;
+                let start_y = vy as usize % height_limit;
+
+                let (sprite_width, sprite_height) = if height == 0 {
+                    (16, 16)
+                } else {
+                    (8, height as usize)
+                };
+                let bytes_per_row = sprite_width / 8;
+                let bytes_per_plane = sprite_height * bytes_per_row;
+
+                //XO-CHIP: each selected plane is drawn in turn with its sprite data following the previous plane's
+                let planes = self.planes;
+                let sprite_size = bytes_per_plane * planes.count_ones() as usize;
+                let sprite =
+                    self.memory[Self::memory_range(self.i_register, sprite_size)?].to_vec();
+                let mut sprite_offset = 0;
+                for plane in [1u8, 2u8].iter().filter(|&&plane| planes & plane != 0) {
+                    for y in 0..sprite_height {
+                        //get the pixel values from the sprite stored in memory, left aligned in 16 bits
+                        let row_offset = sprite_offset + y * bytes_per_row;
+                        let mut pixel = (sprite[row_offset] as u16) << 8;
+                        if bytes_per_row == 2 {
+                            pixel |= sprite[row_offset + 1] as u16;
+                        }
 
-                for y in 0..height {
-                    let pixel = self.memory[(self.i_register + y as usize) as usize]; //get the pixel value from the sprite stored in memory
+                        let mut row = start_y + y;
+                        if row >= height_limit {
+                            if !self.quirks.wrap_sprites {
+                                break;
+                            }
+                            row %= height_limit;
+                        }
+
+                        for x in 0..sprite_width {
+                            if (pixel & (0x8000 >> x)) != 0 {
+                                let mut column = start_x + x;
+                                if column >= width {
+                                    if !self.quirks.wrap_sprites {
+                                        continue;
+                                    }
+                                    column %= width;
+                                }
 
-                    for x in 0..8 {
-                        if (pixel & (0x80 >> x)) != 0 {
-                            let current_position =
-                                ((vx + x) + ((vy + y) * crate::DISPLAY_WIDTH as u16)) as usize; //Treats display as though it were a 2d array
+                                let current_position = column + row * width; //Treats display as though it were a 2d array
 
-                            if current_position < crate::DISPLAY_SIZE {
-                                if self.display[current_position] == 1 {
+                                if self.display[current_position] & plane != 0 {
                                     self.v_register[0xF] = 1; //Collision detected
                                 }
-                                self.display[current_position as usize] ^= 1;
+                                self.display[current_position] ^= plane;
                             }
                         }
                     }
+                    sprite_offset += bytes_per_plane;
                 }
             }
             Opcodes::SkipPressed(vx, sub) => match sub {
                 //Skips the next instruction if the key in Vx is not pressed
                 0x1 => {
-                    if !self.key[self.v_register[vx] as usize] {
-                        self.pc += 2;
+                    if !self.key[(self.v_register[vx] & 0xF) as usize] {
+                        self.skip_next_instruction();
                     }
                 }
                 //Skips the next instruction if the key in Vx is pressed
                 0xE => {
-                    if self.key[self.v_register[vx] as usize] {
-                        self.pc += 2;
+                    if self.key[(self.v_register[vx] & 0xF) as usize] {
+                        self.skip_next_instruction();
                     }
                 }
-                _ => {
-                    println!("Skip Pressed sub code does not exist: {} ", sub)
-                }
+                _ => return Err(invalid_opcode),
             },
-            Opcodes::Misc(vx) => {
-                let subcode = self.opcode & SUB_OPCODE_MASK2;
-
+            Opcodes::Misc(vx, subcode) => {
                 match subcode {
+                    //F000 NNNN: XO-CHIP load I with the 16 bit address in the next two bytes
+                    0x0000 => {
+                        let addr = &self.memory[Self::memory_range(self.pc, 2)?];
+                        self.i_register = (addr[0] as usize) << 8 | addr[1] as usize;
+                        self.pc += 2;
+                    }
+                    //FN01: XO-CHIP select the bitplanes N used by drawing, clearing and scrolling
+                    0x0001 => {
+                        self.planes = vx as u8 & PLANE_MASK;
+                    }
+                    //F002: XO-CHIP load the 16 byte audio pattern starting at I
+                    0x0002 => {
+                        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+                        pattern.copy_from_slice(
+                            &self.memory[Self::memory_range(self.i_register, AUDIO_PATTERN_SIZE)?],
+                        );
+                        self.audio_pattern = Some(pattern);
+                    }
                     //load delay timer
                     0x0007 => {
                         self.v_register[vx] = self.delay_timer;
@@ -379,6 +557,7 @@ impl ChipEight {
                         if !key_found {
                             //So a pretty easy way to wait is to just repeat the same instruction
                             self.pc -= 2;
+                            return Ok(StepOutcome::WaitingForKey);
                         }
                     }
                     0x0015 => {
@@ -390,55 +569,998 @@ impl ChipEight {
                     0x001E => {
                         self.i_register += self.v_register[vx] as usize;
                     }
+                    //FX3A: XO-CHIP set the audio pattern playback pitch to Vx
+                    0x003A => {
+                        self.audio_pitch = self.v_register[vx];
+                    }
                     0x0029 => {
                         //Sets I to the location of the sprite for the character in VX. Characters 0-F (in hexadecimal) are represented by a 4x5 font.
                         //Each sprite is 5 bytes tall
-                        self.i_register = SPRITE_START_ADDR + (5 * self.v_register[vx]) as usize;
+                        self.i_register =
+                            SPRITE_START_ADDR + 5 * (self.v_register[vx] & 0xF) as usize;
+                    }
+                    0x0030 => {
+                        //FX30: SUPER-CHIP sets I to the location of the 8x10 sprite for the digit in VX
+                        self.i_register =
+                            BIG_SPRITE_START_ADDR + 10 * (self.v_register[vx] & 0xF) as usize;
                     }
                     0x0033 => {
                         //FX33: Stores the binary-coded decimal representation of VX,
-                        self.memory[self.i_register] = self.v_register[vx] / 100;
-                        self.memory[self.i_register + 1] = (self.v_register[vx] / 10) % 10;
-                        self.memory[self.i_register + 2] = (self.v_register[vx] % 100) % 10;
+                        let value = self.v_register[vx];
+                        let memory = &mut self.memory[Self::memory_range(self.i_register, 3)?];
+                        memory[0] = value / 100;
+                        memory[1] = (value / 10) % 10;
+                        memory[2] = (value % 100) % 10;
                     }
                     0x0055 => {
-                        //Stores V0 to VX (including VX) in memory starting at address I. The offset from I is increased by 1 for each value written, but I itself is left unmodified.
-                        for x in 0..vx + 1 {
-                            self.memory[self.i_register + x] = self.v_register[x];
-                        }
+                        //Stores V0 to VX (including VX) in memory starting at address I.  Whether I moves afterwards depends on the quirks
+                        let memory = &mut self.memory[Self::memory_range(self.i_register, vx + 1)?];
+                        memory.copy_from_slice(&self.v_register[..vx + 1]);
+                        self.i_register += self.quirks.load_store_increment.amount(vx);
                     }
                     0x0065 => {
-                        for x in 0..vx + 1 {
-                            self.v_register[x] = self.memory[self.i_register + x];
-                        }
+                        let memory = &self.memory[Self::memory_range(self.i_register, vx + 1)?];
+                        self.v_register[..vx + 1].copy_from_slice(memory);
+                        self.i_register += self.quirks.load_store_increment.amount(vx);
                     }
-                    _ => {
-                        println!("Fxxx Opcode bad subcode: {}", subcode)
-                    } //Call machine code routine
+                    0x0075 => {
+                        //FX75: SUPER-CHIP stores V0 to VX in the RPL user flags
+                        self.rpl_flags[..vx + 1].copy_from_slice(&self.v_register[..vx + 1]);
+                        self.rpl_flags_dirty = true;
+                    }
+                    0x0085 => {
+                        //FX85: SUPER-CHIP loads V0 to VX from the RPL user flags
+                        self.v_register[..vx + 1].copy_from_slice(&self.rpl_flags[..vx + 1]);
+                    }
+                    _ => return Err(invalid_opcode),
                 }
             }
-            Opcodes::BadOpcode => println!("Invalid opcode {}", self.opcode),
+            Opcodes::BadOpcode => return Err(invalid_opcode),
         }
 
-        //update timers
+        Ok(StepOutcome::Executed)
+    }
+
+    //Read only views of the machine for debuggers and tools
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn i_register(&self) -> usize {
+        self.i_register
+    }
+
+    pub fn v_registers(&self) -> &[u8; 16] {
+        &self.v_register
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    //Return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    //The opcode that will execute next, if the PC is inside memory
+    pub fn next_opcode(&self) -> Option<u16> {
+        let bytes = self.memory.get(self.pc..self.pc + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    //The delay and sound timers count down at 60Hz independent of how fast instructions execute.
+    //The frontend is expected to call this TIMER_FREQUENCY times per second.
+    pub fn tick_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                println!("BEEP!");
-            }
             self.sound_timer -= 1;
         }
     }
 
-    pub fn load_rom(&mut self, file_path: &str) {
-        let mut rom = File::open(file_path).expect("Rom was not found");
-        let mut buffer = [0; 3584];
-        let buffer_size = rom.read(&mut buffer[..]).expect("Error when reading file");
-        for i in 0..buffer_size {
-            self.memory[i + 512] = buffer[i];
+    //The buzzer sounds for as long as the sound timer is non-zero
+    pub fn sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn audio_pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn audio_pitch(&self) -> u8 {
+        self.audio_pitch
+    }
+
+    //True if an instruction or a loaded state changed the display since the last call.  Frontends use this
+    //to skip redrawing frames where nothing moved
+    pub fn take_display_dirty(&mut self) -> bool {
+        mem::replace(&mut self.display_dirty, false)
+    }
+
+    pub fn display_width(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_WIDTH
+        } else {
+            DISPLAY_WIDTH
+        }
+    }
+
+    pub fn display_height(&self) -> usize {
+        if self.hires {
+            HIRES_DISPLAY_HEIGHT
+        } else {
+            DISPLAY_HEIGHT
+        }
+    }
+
+    //Shifts the selected planes of the display by dx, dy pixels.  Pixels scrolled in from the edge are blank
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.display_width() as isize;
+        let height = self.display_height() as isize;
+        let mut scrolled: Vec<u8> = self
+            .display
+            .iter()
+            .map(|pixel| pixel & !self.planes)
+            .collect();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                if source_x >= 0 && source_x < width && source_y >= 0 && source_y < height {
+                    scrolled[(x + y * width) as usize] |=
+                        self.display[(source_x + source_y * width) as usize] & self.planes;
+                }
+            }
+        }
+
+        self.display = scrolled;
+        self.display_dirty = true;
+    }
+
+    //Bounds checked range of len bytes of memory starting at addr
+    fn memory_range(addr: usize, len: usize) -> Result<Range<usize>, ChipError> {
+        if addr + len > MEMORY_SIZE {
+            return Err(ChipError::MemoryOutOfBounds(addr.max(MEMORY_SIZE)));
+        }
+        Ok(addr..addr + len)
+    }
+
+    //Skips over the next instruction.  XO-CHIP F000 NNNN is four bytes long so it has to be skipped whole
+    fn skip_next_instruction(&mut self) {
+        if self.memory.get(self.pc..self.pc + 2) == Some(&[0xF0, 0x00]) {
+            self.pc += 4;
+        } else {
+            self.pc += 2;
+        }
+    }
+
+    //Registers X to Y inclusive for 5XY2/5XY3.  Runs backwards when X is greater than Y
+    fn register_range(vx: usize, vy: usize) -> Box<dyn Iterator<Item = usize>> {
+        if vx <= vy {
+            Box::new(vx..=vy)
+        } else {
+            Box::new((vy..=vx).rev())
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    //True once the ROM has executed 00FD
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    //Switching resolution resizes the framebuffer and clears it
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.display = vec![0; self.display_width() * self.display_height()];
+        self.display_dirty = true;
+    }
+
+    pub fn load_rom(&mut self, file_path: &str) -> Result<(), ChipError> {
+        let mut rom = Vec::new();
+        File::open(file_path)?.read_to_end(&mut rom)?;
+        self.load_rom_data(&rom)
+    }
+
+    pub fn load_rom_data(&mut self, rom: &[u8]) -> Result<(), ChipError> {
+        if rom.len() > MEMORY_SIZE - PROGRAM_START_ADDR {
+            return Err(ChipError::RomTooLarge(rom.len()));
+        }
+        self.memory[PROGRAM_START_ADDR..PROGRAM_START_ADDR + rom.len()].copy_from_slice(rom);
+        self.rom_hash = rom_hash(rom);
+        Ok(())
+    }
+
+    //Serializes the whole machine, with a header identifying the format version and ROM
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(SAVE_STATE_MAGIC);
+        state.u16(SAVE_STATE_VERSION);
+        state.u64(self.rom_hash);
+
+        state.u16(self.opcode);
+        state.bytes(&self.memory);
+        state.bytes(&self.v_register);
+        state.u32(self.i_register as u32);
+        state.u32(self.pc as u32);
+        state.u8(self.delay_timer);
+        state.u8(self.sound_timer);
+        state.bytes(&self.rpl_flags);
+        state.bool(self.audio_pattern.is_some());
+        state.bytes(&self.audio_pattern.unwrap_or([0; AUDIO_PATTERN_SIZE]));
+        state.u8(self.audio_pitch);
+        for &addr in self.stack.iter() {
+            state.u16(addr);
+        }
+        state.u8(self.sp as u8);
+        for &pressed in self.key.iter() {
+            state.bool(pressed);
+        }
+        state.bool(self.hires);
+        state.u8(self.planes);
+        state.bool(self.exited);
+        state.bytes(&self.display);
+        state.u64(self.seed);
+        state.u64(self.rng.state());
+
+        state.into_bytes()
+    }
+
+    //Restores a state made by save_state.  The machine is left untouched if the state can't be used
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), ChipError> {
+        let mut state = StateReader::new(data);
+        if state.bytes(SAVE_STATE_MAGIC.len())? != SAVE_STATE_MAGIC {
+            return Err(ChipError::InvalidSaveState("not a save state"));
+        }
+        let version = state.u16()?;
+        if version == 0 || version > SAVE_STATE_VERSION {
+            return Err(ChipError::InvalidSaveState("unsupported version"));
+        }
+        if state.u64()? != self.rom_hash {
+            return Err(ChipError::SaveStateRomMismatch);
+        }
+
+        let mut restored = Self::new(self.quirks);
+        restored.rom_hash = self.rom_hash;
+        restored.opcode = state.u16()?;
+        restored.memory.copy_from_slice(state.bytes(MEMORY_SIZE)?);
+        restored.v_register.copy_from_slice(state.bytes(16)?);
+        restored.i_register = state.u32()? as usize;
+        restored.pc = state.u32()? as usize;
+        restored.delay_timer = state.u8()?;
+        restored.sound_timer = state.u8()?;
+        restored
+            .rpl_flags
+            .copy_from_slice(state.bytes(RPL_FLAG_COUNT)?);
+        let has_audio_pattern = state.bool()?;
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern.copy_from_slice(state.bytes(AUDIO_PATTERN_SIZE)?);
+        restored.audio_pattern = if has_audio_pattern {
+            Some(pattern)
+        } else {
+            None
+        };
+        restored.audio_pitch = state.u8()?;
+        for addr in restored.stack.iter_mut() {
+            *addr = state.u16()?;
+        }
+        restored.sp = state.u8()? as usize;
+        if restored.sp > restored.stack.len() {
+            return Err(ChipError::InvalidSaveState("stack pointer out of range"));
+        }
+        for pressed in restored.key.iter_mut() {
+            *pressed = state.bool()?;
+        }
+        restored.hires = state.bool()?;
+        restored.planes = state.u8()? & PLANE_MASK;
+        restored.exited = state.bool()?;
+        let display_size = restored.display_width() * restored.display_height();
+        restored.display = state.bytes(display_size)?.to_vec();
+
+        //Version 1 states predate the seeded generator and keep the current one
+        if version >= 2 {
+            restored.seed = state.u64()?;
+            restored.rng = Random::from_state(state.u64()?);
+        } else {
+            restored.seed = self.seed;
+            restored.rng = self.rng;
+        }
+
+        *self = restored;
+        Ok(())
+    }
+
+    //Identifies the loaded ROM so per-ROM data like RPL flags can be stored on disk
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; RPL_FLAG_COUNT] {
+        &self.rpl_flags
+    }
+
+    //True if FX75 wrote the flags since the last call.  Restoring a state or calling set_rpl_flags doesn't
+    //count, so frontends only persist flags the ROM itself saved
+    pub fn take_rpl_flags_dirty(&mut self) -> bool {
+        mem::replace(&mut self.rpl_flags_dirty, false)
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; RPL_FLAG_COUNT]) {
+        self.rpl_flags = flags;
+    }
+
+    //Restarts the CXNN generator so the same seed always produces the same numbers
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = Random::new(seed);
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+//64 bit FNV-1a.  Used instead of std's hasher because the value is written to disk and must not change between builds
+pub fn rom_hash(rom: &[u8]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    for &byte in rom {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    //A machine with program loaded at 0x200.  The seed is fixed so CXNN is repeatable.  Shared with the
+    //tests of the other modules that need a running machine
+    pub(crate) fn machine_with(quirks: Quirks, program: &[u16]) -> ChipEight {
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = ChipEight::new(quirks);
+        chip8.set_seed(0);
+        chip8.load_rom_data(&rom).unwrap();
+        chip8
+    }
+
+    pub(crate) fn machine(program: &[u16]) -> ChipEight {
+        machine_with(Quirks::default(), program)
+    }
+
+    fn step(chip8: &mut ChipEight, count: usize) {
+        for _ in 0..count {
+            chip8.emulation_cycle().unwrap();
+        }
+    }
+
+    //Runs every instruction of a program with no jumps once
+    fn run(program: &[u16]) -> ChipEight {
+        run_with(Quirks::default(), program)
+    }
+
+    fn run_with(quirks: Quirks, program: &[u16]) -> ChipEight {
+        let mut chip8 = machine_with(quirks, program);
+        step(&mut chip8, program.len());
+        chip8
+    }
+
+    fn pixel(chip8: &ChipEight, x: usize, y: usize) -> u8 {
+        chip8.display[x + y * chip8.display_width()]
+    }
+
+    fn lit_pixels(chip8: &ChipEight) -> usize {
+        chip8.display.iter().filter(|&&pixel| pixel != 0).count()
+    }
+
+    //Puts a one pixel sprite at 0x300 and points I at it
+    fn dot_sprite(chip8: &mut ChipEight) {
+        chip8.memory[0x300] = 0x80;
+        chip8.i_register = 0x300;
+    }
+
+    #[test]
+    fn clear_screen_00e0() {
+        let mut chip8 = machine(&[0x00E0]);
+        chip8.display[5] = 1;
+        step(&mut chip8, 1);
+        assert_eq!(lit_pixels(&chip8), 0);
+    }
+
+    #[test]
+    fn call_2nnn_and_return_00ee() {
+        //0x200 call 0x206, 0x202 V1 = 5, 0x204 loop, 0x206 V0 = 7, 0x208 return
+        let mut chip8 = machine(&[0x2206, 0x6105, 0x1204, 0x6007, 0x00EE]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc(), 0x206);
+        assert_eq!(chip8.stack(), &[0x202]);
+        step(&mut chip8, 2);
+        assert_eq!(chip8.pc(), 0x202);
+        assert!(chip8.stack().is_empty());
+        step(&mut chip8, 1);
+        assert_eq!(chip8.v_registers()[0], 7);
+        assert_eq!(chip8.v_registers()[1], 5);
+    }
+
+    #[test]
+    fn return_with_empty_stack_is_an_error() {
+        let mut chip8 = machine(&[0x00EE]);
+        assert!(matches!(
+            chip8.emulation_cycle(),
+            Err(ChipError::StackUnderflow)
+        ));
+    }
+
+    #[test]
+    fn call_overflows_after_16_levels() {
+        let mut chip8 = machine(&[0x2200]);
+        step(&mut chip8, 16);
+        assert!(matches!(
+            chip8.emulation_cycle(),
+            Err(ChipError::StackOverflow)
+        ));
+    }
+
+    #[test]
+    fn machine_code_call_0nnn_is_invalid() {
+        let mut chip8 = machine(&[0x0123]);
+        assert!(matches!(
+            chip8.emulation_cycle(),
+            Err(ChipError::InvalidOpcode(0x0123, 0x200))
+        ));
+    }
+
+    #[test]
+    fn jump_1nnn() {
+        assert_eq!(run(&[0x1ABC]).pc(), 0xABC);
+    }
+
+    #[test]
+    fn skip_3xnn() {
+        assert_eq!(run(&[0x6005, 0x3005]).pc(), 0x206);
+        assert_eq!(run(&[0x6005, 0x3006]).pc(), 0x204);
+    }
+
+    #[test]
+    fn skip_4xnn() {
+        assert_eq!(run(&[0x6005, 0x4005]).pc(), 0x204);
+        assert_eq!(run(&[0x6005, 0x4006]).pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_5xy0() {
+        assert_eq!(run(&[0x6005, 0x6105, 0x5010]).pc(), 0x208);
+        assert_eq!(run(&[0x6005, 0x6106, 0x5010]).pc(), 0x206);
+    }
+
+    #[test]
+    fn skip_9xy0() {
+        assert_eq!(run(&[0x6005, 0x6105, 0x9010]).pc(), 0x206);
+        assert_eq!(run(&[0x6005, 0x6106, 0x9010]).pc(), 0x208);
+    }
+
+    #[test]
+    fn skips_step_over_four_byte_f000() {
+        let mut chip8 = machine(&[0x3000, 0xF000, 0x1234]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.pc(), 0x206);
+    }
+
+    #[test]
+    fn load_6xnn_and_add_7xnn() {
+        let chip8 = run(&[0x6A12, 0x7A34]);
+        assert_eq!(chip8.v_registers()[0xA], 0x46);
+    }
+
+    #[test]
+    fn add_7xnn_wraps_without_touching_vf() {
+        let chip8 = run(&[0x6F55, 0x60FF, 0x7002]);
+        assert_eq!(chip8.v_registers()[0], 0x01);
+        assert_eq!(chip8.v_registers()[0xF], 0x55);
+    }
+
+    #[test]
+    fn assign_and_logic_8xy0_to_8xy3() {
+        assert_eq!(run(&[0x6107, 0x8010]).v_registers()[0], 0x07);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8011]).v_registers()[0], 0x0E);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8012]).v_registers()[0], 0x08);
+        assert_eq!(run(&[0x600C, 0x610A, 0x8013]).v_registers()[0], 0x06);
+    }
+
+    #[test]
+    fn add_8xy4_sets_carry() {
+        let chip8 = run(&[0x60FF, 0x6102, 0x8014]);
+        assert_eq!(chip8.v_registers()[0], 0x01);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        let chip8 = run(&[0x6010, 0x6120, 0x8014]);
+        assert_eq!(chip8.v_registers()[0], 0x30);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn add_8xy4_carry_depends_on_vy() {
+        //0x90 + 0x10 doesn't carry even though 0x90 + 0x90 would
+        let chip8 = run(&[0x6090, 0x6110, 0x8014]);
+        assert_eq!(chip8.v_registers()[0], 0xA0);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        assert_eq!(run(&[0x6FFF, 0x6101, 0x8F14]).v_registers()[0xF], 1);
+        assert_eq!(run(&[0x6F01, 0x6102, 0x8F15]).v_registers()[0xF], 0);
+        assert_eq!(run(&[0x6F03, 0x8FF6]).v_registers()[0xF], 1);
+        assert_eq!(run(&[0x6F40, 0x8FFE]).v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn sub_8xy5_sets_not_borrow() {
+        let chip8 = run(&[0x6005, 0x6103, 0x8015]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        let chip8 = run(&[0x6003, 0x6105, 0x8015]);
+        assert_eq!(chip8.v_registers()[0], 0xFE);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+
+        let chip8 = run(&[0x6005, 0x6105, 0x8015]);
+        assert_eq!(chip8.v_registers()[0], 0x00);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn subn_8xy7_sets_not_borrow() {
+        let chip8 = run(&[0x6003, 0x6105, 0x8017]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        let chip8 = run(&[0x6005, 0x6103, 0x8017]);
+        assert_eq!(chip8.v_registers()[0], 0xFE);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+
+        let chip8 = run(&[0x6005, 0x6105, 0x8017]);
+        assert_eq!(chip8.v_registers()[0], 0x00);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn shift_right_8xy6() {
+        let chip8 = run(&[0x6005, 0x8016]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        let chip8 = run(&[0x6004, 0x8016]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn shift_left_8xye() {
+        let chip8 = run(&[0x6081, 0x801E]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        //Bit 4 set must not leak into the flag
+        let chip8 = run(&[0x6010, 0x801E]);
+        assert_eq!(chip8.v_registers()[0], 0x20);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn shifts_use_vy_with_the_vip_quirk() {
+        let chip8 = run_with(Quirks::cosmac_vip(), &[0x6000, 0x6103, 0x8016]);
+        assert_eq!(chip8.v_registers()[0], 0x01);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+
+        let chip8 = run_with(Quirks::cosmac_vip(), &[0x6000, 0x6181, 0x801E]);
+        assert_eq!(chip8.v_registers()[0], 0x02);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn unknown_8xyn_is_invalid() {
+        let mut chip8 = machine(&[0x8018]);
+        assert!(matches!(
+            chip8.emulation_cycle(),
+            Err(ChipError::InvalidOpcode(0x8018, 0x200))
+        ));
+    }
+
+    #[test]
+    fn load_i_annn() {
+        assert_eq!(run(&[0xA123]).i_register(), 0x123);
+    }
+
+    #[test]
+    fn jump_offset_bnnn() {
+        assert_eq!(run(&[0x6010, 0x6220, 0xB300]).pc(), 0x310);
+        assert_eq!(
+            run_with(Quirks::chip48(), &[0x6010, 0x6320, 0xB300]).pc(),
+            0x320
+        );
+    }
+
+    #[test]
+    fn random_cxnn_is_masked_and_seeded() {
+        let a = run(&[0xC00F, 0xC1FF, 0xC2FF]);
+        let b = run(&[0xC00F, 0xC1FF, 0xC2FF]);
+        assert!(a.v_registers()[0] <= 0x0F);
+        assert_eq!(a.v_registers(), b.v_registers());
+        assert_eq!(run(&[0xC000]).v_registers()[0], 0);
+    }
+
+    #[test]
+    fn draw_dxyn_font_digit() {
+        //Digit 0 is F0 90 90 90 F0
+        let chip8 = run(&[0x6000, 0xF029, 0xD005]);
+        assert_eq!(lit_pixels(&chip8), 14);
+        assert_eq!(pixel(&chip8, 0, 0), 1);
+        assert_eq!(pixel(&chip8, 3, 0), 1);
+        assert_eq!(pixel(&chip8, 1, 1), 0);
+        assert_eq!(pixel(&chip8, 3, 4), 1);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn draw_dxyn_sets_collision_and_erases() {
+        let chip8 = run(&[0x6000, 0xF029, 0xD005, 0xD005]);
+        assert_eq!(lit_pixels(&chip8), 0);
+        assert_eq!(chip8.v_registers()[0xF], 1);
+    }
+
+    #[test]
+    fn draw_dxyn_collision_is_cleared_by_the_next_draw() {
+        let chip8 = run(&[0x6000, 0xF029, 0xD005, 0xD005, 0xD005]);
+        assert_eq!(chip8.v_registers()[0xF], 0);
+    }
+
+    #[test]
+    fn draw_dxyn_clips_or_wraps_at_the_edge() {
+        //Row 0 of digit 0 is 4 pixels wide, so 2 fall off the right edge at x = 62
+        let program = [0x6000, 0xF029, 0x613E, 0xD101];
+        let clipped = run(&program);
+        assert_eq!(lit_pixels(&clipped), 2);
+        assert_eq!(pixel(&clipped, 0, 0), 0);
+
+        let quirks = Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        };
+        let wrapped = run_with(quirks, &program);
+        assert_eq!(lit_pixels(&wrapped), 4);
+        assert_eq!(pixel(&wrapped, 0, 0), 1);
+        assert_eq!(pixel(&wrapped, 1, 0), 1);
+    }
+
+    #[test]
+    fn draw_dxyn_start_coordinate_wraps() {
+        let mut chip8 = machine(&[0x6041, 0x6122, 0xD011]);
+        dot_sprite(&mut chip8);
+        step(&mut chip8, 3);
+        assert_eq!(pixel(&chip8, 1, 2), 1);
+    }
+
+    #[test]
+    fn draw_dxy0_is_16x16() {
+        let mut chip8 = machine(&[0x00FF, 0xD000]);
+        chip8.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        chip8.i_register = 0x300;
+        step(&mut chip8, 2);
+        assert_eq!(lit_pixels(&chip8), 256);
+        assert_eq!(pixel(&chip8, 15, 15), 1);
+        assert_eq!(pixel(&chip8, 16, 0), 0);
+    }
+
+    #[test]
+    fn skip_if_key_ex9e_and_exa1() {
+        let mut chip8 = machine(&[0x6005, 0xE09E]);
+        chip8.key[5] = true;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.pc(), 0x206);
+
+        assert_eq!(run(&[0x6005, 0xE09E]).pc(), 0x204);
+        assert_eq!(run(&[0x6005, 0xE0A1]).pc(), 0x206);
+
+        let mut chip8 = machine(&[0x6005, 0xE0A1]);
+        chip8.key[5] = true;
+        step(&mut chip8, 2);
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn timers_fx07_fx15_fx18() {
+        let mut chip8 = run(&[0x6033, 0xF015, 0xF018]);
+        assert!(chip8.sound_playing());
+        chip8.tick_timers();
+        assert_eq!(chip8.delay_timer(), 0x32);
+        assert_eq!(chip8.sound_timer(), 0x32);
+
+        let mut chip8 = machine(&[0x6033, 0xF015, 0xF107]);
+        step(&mut chip8, 3);
+        assert_eq!(chip8.v_registers()[1], 0x33);
+    }
+
+    #[test]
+    fn wait_for_key_fx0a() {
+        let mut chip8 = machine(&[0xF30A]);
+        assert_eq!(chip8.emulation_cycle().unwrap(), StepOutcome::WaitingForKey);
+        assert_eq!(chip8.pc(), 0x200);
+
+        chip8.key[0xB] = true;
+        assert_eq!(chip8.emulation_cycle().unwrap(), StepOutcome::Executed);
+        assert_eq!(chip8.v_registers()[3], 0xB);
+        assert_eq!(chip8.pc(), 0x202);
+    }
+
+    #[test]
+    fn add_to_i_fx1e() {
+        assert_eq!(run(&[0xA100, 0x6020, 0xF01E]).i_register(), 0x120);
+    }
+
+    #[test]
+    fn font_addresses_fx29_and_fx30() {
+        assert_eq!(
+            run(&[0x600A, 0xF029]).i_register(),
+            SPRITE_START_ADDR + 5 * 0xA
+        );
+        assert_eq!(
+            run(&[0x6009, 0xF030]).i_register(),
+            BIG_SPRITE_START_ADDR + 10 * 9
+        );
+    }
+
+    #[test]
+    fn bcd_fx33() {
+        let chip8 = run(&[0x60FE, 0xA300, 0xF033]);
+        assert_eq!(&chip8.memory()[0x300..0x303], &[2, 5, 4]);
+        assert_eq!(chip8.i_register(), 0x300);
+
+        let chip8 = run(&[0x6007, 0xA300, 0xF033]);
+        assert_eq!(&chip8.memory()[0x300..0x303], &[0, 0, 7]);
+    }
+
+    #[test]
+    fn store_and_load_fx55_fx65() {
+        let chip8 = run(&[0x6011, 0x6122, 0x6233, 0xA300, 0xF155]);
+        assert_eq!(&chip8.memory()[0x300..0x303], &[0x11, 0x22, 0x00]);
+        assert_eq!(chip8.i_register(), 0x300);
+
+        let mut chip8 = machine(&[0xA300, 0xF265]);
+        chip8.memory[0x300..0x303].copy_from_slice(&[7, 8, 9]);
+        step(&mut chip8, 2);
+        assert_eq!(&chip8.v_registers()[..4], &[7, 8, 9, 0]);
+        assert_eq!(chip8.i_register(), 0x300);
+    }
+
+    #[test]
+    fn store_and_load_increment_i_with_the_vip_quirk() {
+        let chip8 = run_with(Quirks::cosmac_vip(), &[0xA300, 0xF155]);
+        assert_eq!(chip8.i_register(), 0x302);
+        let chip8 = run_with(Quirks::cosmac_vip(), &[0xA300, 0xF265]);
+        assert_eq!(chip8.i_register(), 0x303);
+    }
+
+    #[test]
+    fn store_increments_i_per_profile() {
+        let program = [0xA300, 0xF255];
+        assert_eq!(run(&program).i_register(), 0x300);
+        assert_eq!(run_with(Quirks::cosmac_vip(), &program).i_register(), 0x303);
+        assert_eq!(run_with(Quirks::chip48(), &program).i_register(), 0x302);
+        assert_eq!(run_with(Quirks::super_chip(), &program).i_register(), 0x300);
+    }
+
+    #[test]
+    fn rpl_flags_fx75_fx85() {
+        let mut chip8 = machine(&[0x6012, 0x6134, 0xF175, 0x6000, 0x6100, 0xF185]);
+        step(&mut chip8, 3);
+        assert_eq!(&chip8.rpl_flags()[..3], &[0x12, 0x34, 0]);
+        step(&mut chip8, 3);
+        assert_eq!(&chip8.v_registers()[..2], &[0x12, 0x34]);
+    }
+
+    #[test]
+    fn rpl_flags_are_dirty_only_after_fx75() {
+        let mut chip8 = machine(&[0x6012, 0xF075]);
+        chip8.set_rpl_flags([1; RPL_FLAG_COUNT]);
+        step(&mut chip8, 1);
+        assert!(!chip8.take_rpl_flags_dirty());
+        let state = chip8.save_state();
+
+        step(&mut chip8, 1);
+        assert!(chip8.take_rpl_flags_dirty());
+        assert!(!chip8.take_rpl_flags_dirty());
+
+        //Restoring older flags from a state isn't a write by the ROM
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.rpl_flags()[0], 1);
+        assert!(!chip8.take_rpl_flags_dirty());
+    }
+
+    #[test]
+    fn resolution_00fe_00ff() {
+        let chip8 = run(&[0x00FF]);
+        assert_eq!(chip8.display_width(), HIRES_DISPLAY_WIDTH);
+        assert_eq!(
+            chip8.display.len(),
+            HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT
+        );
+        let chip8 = run(&[0x00FF, 0x00FE]);
+        assert_eq!(chip8.display_width(), DISPLAY_WIDTH);
+        assert_eq!(chip8.display.len(), DISPLAY_SIZE);
+    }
+
+    #[test]
+    fn scroll_00cn_00dn_00fb_00fc() {
+        let scrolled = |program: &[u16], x: usize, y: usize| {
+            let mut chip8 = machine(program);
+            dot_sprite(&mut chip8);
+            step(&mut chip8, program.len());
+            pixel(&chip8, x, y)
+        };
+        //Each program draws a dot at (V0, V1) and scrolls it
+        assert_eq!(scrolled(&[0xD011, 0x00C2], 0, 2), 1);
+        assert_eq!(scrolled(&[0x6105, 0xD011, 0x00D3], 0, 2), 1);
+        assert_eq!(scrolled(&[0xD011, 0x00FB], 4, 0), 1);
+        assert_eq!(scrolled(&[0x6006, 0xD011, 0x00FC], 2, 0), 1);
+    }
+
+    #[test]
+    fn exit_00fd() {
+        let mut chip8 = machine(&[0x00FD, 0x6001]);
+        assert_eq!(chip8.emulation_cycle().unwrap(), StepOutcome::Exited);
+        assert!(chip8.exited());
+        assert_eq!(chip8.emulation_cycle().unwrap(), StepOutcome::Exited);
+        assert_eq!(chip8.v_registers()[0], 0);
+    }
+
+    #[test]
+    fn save_and_load_range_5xy2_5xy3() {
+        let chip8 = run(&[0x6211, 0x6322, 0x6433, 0xA300, 0x5242]);
+        assert_eq!(&chip8.memory()[0x300..0x303], &[0x11, 0x22, 0x33]);
+        assert_eq!(chip8.i_register(), 0x300);
+
+        //Ranges run backwards when X is greater than Y
+        let mut chip8 = machine(&[0xA300, 0x5423]);
+        chip8.memory[0x300..0x303].copy_from_slice(&[1, 2, 3]);
+        step(&mut chip8, 2);
+        assert_eq!(&chip8.v_registers()[2..5], &[3, 2, 1]);
+    }
+
+    #[test]
+    fn long_load_i_f000() {
+        let mut chip8 = machine(&[0xF000, 0xBEEF]);
+        step(&mut chip8, 1);
+        assert_eq!(chip8.i_register(), 0xBEEF);
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn planes_fn01() {
+        let mut chip8 = machine(&[0xF201, 0xD011, 0xF301, 0x00E0, 0xD011]);
+        dot_sprite(&mut chip8);
+        chip8.memory[0x301] = 0x80;
+        step(&mut chip8, 2);
+        assert_eq!(pixel(&chip8, 0, 0), 0b10);
+
+        //Drawing both planes reads a second sprite for plane 2
+        step(&mut chip8, 3);
+        assert_eq!(pixel(&chip8, 0, 0), 0b11);
+    }
+
+    #[test]
+    fn audio_f002_and_pitch_fx3a() {
+        let mut chip8 = machine(&[0xA300, 0xF002, 0x6080, 0xF03A]);
+        chip8.memory[0x300..0x310].copy_from_slice(&[0xAA; AUDIO_PATTERN_SIZE]);
+        step(&mut chip8, 4);
+        assert_eq!(chip8.audio_pattern(), Some(&[0xAA; AUDIO_PATTERN_SIZE]));
+        assert_eq!(chip8.audio_pitch(), 0x80);
+    }
+
+    #[test]
+    fn memory_access_past_the_end_is_an_error() {
+        let mut chip8 = machine(&[0xF000, 0xFFFF, 0xF033]);
+        step(&mut chip8, 1);
+        assert!(matches!(
+            chip8.emulation_cycle(),
+            Err(ChipError::MemoryOutOfBounds(_))
+        ));
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut chip8 = ChipEight::default();
+        let rom = vec![0; MEMORY_SIZE - PROGRAM_START_ADDR + 1];
+        assert!(matches!(
+            chip8.load_rom_data(&rom),
+            Err(ChipError::RomTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn save_state_round_trip() {
+        let mut chip8 = machine(&[0x6A12, 0x2206, 0x0000, 0xF029, 0xDA05]);
+        step(&mut chip8, 4);
+        let state = chip8.save_state();
+        let expected = (chip8.pc(), chip8.display.clone(), *chip8.v_registers());
+
+        chip8.display[0] ^= 1;
+        chip8.emulation_cycle().unwrap_err();
+        chip8.load_state(&state).unwrap();
+        assert_eq!(
+            (chip8.pc(), chip8.display.clone(), *chip8.v_registers()),
+            expected
+        );
+        assert_eq!(chip8.stack(), &[0x204]);
+    }
+
+    #[test]
+    fn display_dirty_is_set_by_drawing_instructions() {
+        //6000 and A300 leave the display alone, DXYN, 00E0, 00FB and 00FF change it
+        let mut chip8 = machine(&[0x6000, 0xA300, 0xD001, 0x00E0, 0x00FB, 0x00FF]);
+        assert!(chip8.take_display_dirty()); //A new machine has never been drawn
+        step(&mut chip8, 2);
+        assert!(!chip8.take_display_dirty());
+        for _ in 0..4 {
+            step(&mut chip8, 1);
+            assert!(chip8.take_display_dirty());
+            assert!(!chip8.take_display_dirty());
         }
+
+        let state = chip8.save_state();
+        chip8.load_state(&state).unwrap();
+        assert!(chip8.take_display_dirty());
+    }
+
+    #[test]
+    fn save_states_are_checked_before_loading() {
+        let state = machine(&[0x6A12]).save_state();
+        assert!(matches!(
+            machine(&[0x6A13]).load_state(&state),
+            Err(ChipError::SaveStateRomMismatch)
+        ));
+        assert!(matches!(
+            machine(&[0x6A12]).load_state(&state[1..]),
+            Err(ChipError::InvalidSaveState(_))
+        ));
+        assert!(matches!(
+            machine(&[0x6A12]).load_state(&state[..state.len() - 1]),
+            Err(ChipError::InvalidSaveState(_))
+        ));
+    }
+
+    #[test]
+    fn save_states_capture_the_random_generator() {
+        let mut chip8 = machine(&[0xC0FF, 0xC0FF]);
+        step(&mut chip8, 1);
+        let state = chip8.save_state();
+        step(&mut chip8, 1);
+        let second = chip8.v_registers()[0];
+
+        chip8.set_seed(1);
+        chip8.load_state(&state).unwrap();
+        step(&mut chip8, 1);
+        assert_eq!(chip8.v_registers()[0], second);
     }
 }
@@ -0,0 +1,315 @@
+use chip_eight_emulator::audio::{self, AudioSettings, Waveform};
+use chip_eight_emulator::chip_eight::{DEFAULT_INSTRUCTIONS_PER_FRAME, TIMER_FREQUENCY};
+use chip_eight_emulator::headless;
+use chip_eight_emulator::movie::KeyChange;
+use chip_eight_emulator::palette::Palette;
+use chip_eight_emulator::quirks::{self, Quirks};
+
+use std::str::FromStr;
+
+const DEFAULT_SCALE: usize = 10;
+const DEFAULT_REWIND_SECONDS: usize = 30;
+//Audible range for the beep
+const MIN_FREQUENCY: f32 = 20.0;
+const MAX_FREQUENCY: f32 = 20_000.0;
+
+//Everything the command line can change about how a ROM is run
+pub struct Options {
+    pub rom_path: String,
+    pub scale: usize, //window pixels per Chip8 pixel
+    pub instructions_per_frame: u32,
+    pub quirks: Quirks,
+    pub palette: Palette,
+    pub audio: AudioSettings, //Beep waveform, frequency, volume and whether it starts muted
+    //Brightness a dark pixel keeps each frame.  None draws the display raw
+    pub phosphor: Option<f32>,
+    pub rewind_seconds: usize, //How far back Backspace can go.  0 turns rewind off
+    pub headless: bool,        //Run without a window or sound
+    //Stop after this many frames.  None runs until the user quits or the ROM exits
+    pub frames: Option<u32>,
+    pub seed: Option<u64>,            //CXNN seed.  None seeds from the OS
+    pub record: Option<String>,       //Movie file to record the keypad into
+    pub play: Option<String>,         //Movie file to replay
+    pub keys: Option<Vec<KeyChange>>, //Scripted headless input
+    //Where to write the display after a headless run.  - prints it
+    pub dump: Option<String>,
+    pub keymap: Option<String>, //Keymap config file.  None uses the one in the data directory
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    Help,
+}
+
+//Parses the arguments after the program name.  Errors are messages for the user
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        scale: DEFAULT_SCALE,
+        instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+        quirks: Quirks::default(),
+        palette: Palette::default(),
+        audio: AudioSettings::default(),
+        phosphor: None,
+        rewind_seconds: DEFAULT_REWIND_SECONDS,
+        headless: false,
+        frames: None,
+        seed: None,
+        record: None,
+        play: None,
+        keys: None,
+        dump: None,
+        keymap: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        //Values can be given as --flag value or --flag=value
+        let (flag, inline_value) = match arg.find('=') {
+            Some(equals) if arg.starts_with("--") => (&arg[..equals], Some(&arg[equals + 1..])),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || match inline_value {
+            Some(value) => Ok(value),
+            None => args
+                .next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("{} needs a value", flag)),
+        };
+
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--scale" => {
+                options.scale = number(flag, value()?)?;
+                if options.scale == 0 {
+                    return Err("--scale must be at least 1".to_string());
+                }
+            }
+            "--ipf" => {
+                options.instructions_per_frame = number(flag, value()?)?;
+                if options.instructions_per_frame == 0 {
+                    return Err("--ipf must be at least 1".to_string());
+                }
+            }
+            "--cpu-hz" => {
+                //Rounded to the nearest whole number of instructions per frame
+                let hz: u32 = number(flag, value()?)?;
+                options.instructions_per_frame =
+                    (hz as f64 / TIMER_FREQUENCY as f64).round() as u32;
+                if options.instructions_per_frame == 0 {
+                    return Err(format!("--cpu-hz must be at least {}", TIMER_FREQUENCY / 2));
+                }
+            }
+            "--quirks" => {
+                let profile = value()?;
+                options.quirks = Quirks::from_profile(profile).ok_or_else(|| {
+                    format!(
+                        "unknown quirks profile '{}'.  Expected one of: {}",
+                        profile,
+                        quirks::PROFILES.join(", ")
+                    )
+                })?;
+            }
+            "--palette" => {
+                options.palette =
+                    Palette::parse(value()?).map_err(|e| format!("--palette: {}", e))?;
+            }
+            "--mute" => options.audio.muted = true,
+            "--waveform" => {
+                let name = value()?;
+                options.audio.waveform = Waveform::from_name(name).ok_or_else(|| {
+                    format!(
+                        "unknown waveform '{}'.  Expected one of: {}",
+                        name,
+                        audio::WAVEFORMS.join(", ")
+                    )
+                })?;
+            }
+            "--frequency" => {
+                let frequency = value()?;
+                options.audio.frequency = match frequency.parse() {
+                    Ok(hz) if (MIN_FREQUENCY..=MAX_FREQUENCY).contains(&hz) => hz,
+                    _ => {
+                        return Err(format!(
+                            "--frequency expects {} to {} Hz, found '{}'",
+                            MIN_FREQUENCY, MAX_FREQUENCY, frequency
+                        ))
+                    }
+                };
+            }
+            "--volume" => {
+                let volume = value()?;
+                options.audio.volume = match volume.parse() {
+                    Ok(volume) if (0.0..=1.0).contains(&volume) => volume,
+                    _ => {
+                        return Err(format!(
+                            "--volume expects a value from 0 to 1, found '{}'",
+                            volume
+                        ))
+                    }
+                };
+            }
+            "--phosphor" => {
+                let decay = value()?;
+                options.phosphor = match decay.parse() {
+                    Ok(decay) if (0.0..1.0).contains(&decay) => Some(decay),
+                    _ => {
+                        return Err(format!(
+                            "--phosphor expects a decay from 0 up to 1, found '{}'",
+                            decay
+                        ))
+                    }
+                };
+            }
+            "--rewind" => options.rewind_seconds = number(flag, value()?)?,
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(number(flag, value()?)?),
+            "--seed" => options.seed = Some(number(flag, value()?)?),
+            "--record" => options.record = Some(value()?.to_string()),
+            "--play" => options.play = Some(value()?.to_string()),
+            "--keys" => {
+                options.keys = Some(
+                    headless::parse_key_script(value()?).map_err(|e| format!("--keys: {}", e))?,
+                );
+            }
+            "--dump" => options.dump = Some(value()?.to_string()),
+            "--keymap" => options.keymap = Some(value()?.to_string()),
+            _ if flag.starts_with('-') => return Err(format!("unknown option {}", flag)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if options.record.is_some() && options.play.is_some() {
+        return Err("--record and --play can't be used together".to_string());
+    }
+    if options.record.is_some() && options.headless {
+        return Err("--record needs a window to take input from".to_string());
+    }
+    if options.keys.is_some() && options.play.is_some() {
+        return Err("--keys and --play can't be used together".to_string());
+    }
+    if options.headless && options.frames.is_none() && options.play.is_none() {
+        return Err("--headless needs --frames or --play to know when to stop".to_string());
+    }
+    if (options.keys.is_some() || options.dump.is_some()) && !options.headless {
+        return Err("--keys and --dump only work with --headless".to_string());
+    }
+    options.rom_path = rom_path.ok_or_else(|| "no ROM given".to_string())?;
+    Ok(Command::Run(Box::new(options)))
+}
+
+fn number<T: FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a whole number, found '{}'", flag, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(args: &str) -> Result<Command, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        parse(&args)
+    }
+
+    fn options(args: &str) -> Options {
+        match parse_str(args) {
+            Ok(Command::Run(options)) => *options,
+            Ok(Command::Help) => panic!("'{}' asked for help", args),
+            Err(e) => panic!("'{}' failed: {}", args, e),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        match parse_str(args) {
+            Err(e) => e,
+            Ok(_) => panic!("'{}' should not parse", args),
+        }
+    }
+
+    #[test]
+    fn defaults_need_only_a_rom() {
+        let options = options("pong.ch8");
+        assert_eq!(options.rom_path, "pong.ch8");
+        assert_eq!(options.scale, DEFAULT_SCALE);
+        assert_eq!(
+            options.instructions_per_frame,
+            DEFAULT_INSTRUCTIONS_PER_FRAME
+        );
+        assert_eq!(options.quirks, Quirks::default());
+        assert_eq!(options.audio, AudioSettings::default());
+        assert_eq!(options.rewind_seconds, DEFAULT_REWIND_SECONDS);
+        assert!(!options.headless);
+        assert_eq!(error(""), "no ROM given");
+    }
+
+    #[test]
+    fn values_follow_the_flag_or_an_equals_sign() {
+        let options = options("--scale 4 --quirks=vip pong.ch8 --seed=99 --frames 60 --headless");
+        assert_eq!(options.scale, 4);
+        assert_eq!(options.quirks, Quirks::cosmac_vip());
+        assert_eq!(options.seed, Some(99));
+        assert_eq!(options.frames, Some(60));
+        assert!(options.headless);
+        assert!(matches!(parse_str("pong.ch8 -h"), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn cpu_hz_rounds_to_whole_instructions_per_frame() {
+        assert_eq!(options("--cpu-hz 1000 a").instructions_per_frame, 17);
+        assert_eq!(options("--cpu-hz 600 a").instructions_per_frame, 10);
+        assert_eq!(options("--cpu-hz 30 a").instructions_per_frame, 1);
+        assert_eq!(error("--cpu-hz 29 a"), "--cpu-hz must be at least 30");
+        assert_eq!(error("--ipf 0 a"), "--ipf must be at least 1");
+    }
+
+    #[test]
+    fn audio_options() {
+        let options = options("--mute --waveform Sine --frequency 220 --volume 1 a");
+        assert!(options.audio.muted);
+        assert_eq!(options.audio.waveform, Waveform::Sine);
+        assert_eq!(options.audio.frequency, 220.0);
+        assert_eq!(options.audio.volume, 1.0);
+        assert!(error("--waveform noise a").starts_with("unknown waveform 'noise'"));
+        assert!(error("--frequency 5 a").starts_with("--frequency expects"));
+        assert!(error("--volume 1.5 a").starts_with("--volume expects"));
+    }
+
+    #[test]
+    fn bad_values_are_explained() {
+        assert_eq!(
+            error("--scale big a"),
+            "--scale expects a whole number, found 'big'"
+        );
+        assert_eq!(error("a --scale"), "--scale needs a value");
+        assert!(error("--quirks nes a").starts_with("unknown quirks profile 'nes'"));
+        assert!(error("--phosphor 1 a").starts_with("--phosphor expects"));
+        assert_eq!(error("--fast a"), "unknown option --fast");
+        assert_eq!(error("a b"), "unexpected argument b");
+    }
+
+    #[test]
+    fn conflicting_options_are_rejected() {
+        assert_eq!(
+            error("--headless a"),
+            "--headless needs --frames or --play to know when to stop"
+        );
+        assert!(options("--headless --play run.c8mv a").headless);
+        assert_eq!(
+            error("--record a.c8mv --play b.c8mv a"),
+            "--record and --play can't be used together"
+        );
+        assert_eq!(
+            error("--headless --frames 1 --record a.c8mv a"),
+            "--record needs a window to take input from"
+        );
+        assert_eq!(
+            error("--keys 1:2 a"),
+            "--keys and --dump only work with --headless"
+        );
+        assert!(error("--headless --frames 1 --keys 1:z a").starts_with("--keys: "));
+    }
+}
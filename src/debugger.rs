@@ -0,0 +1,598 @@
+use crate::chip_eight::{ChipEight, StepOutcome};
+use crate::error::ChipError;
+
+//Step debugger driven by text commands.  The frontend asks before_instruction whether it may run the next
+//instruction, calls after_instruction once it has, and end_frame once a frame has run all its instructions.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RunState {
+    Running,
+    Paused,
+    Stepping(usize), //Instructions left to run before pausing again
+    FrameStep,       //Run to the end of the current frame then pause
+}
+
+//Matches opcodes written like "DXYN" or "8XY4".  Hex digits must match, anything else is a wildcard
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodePattern {
+    mask: u16,
+    value: u16,
+    text: [u8; 4],
+}
+
+impl OpcodePattern {
+    pub fn parse(text: &str) -> Option<Self> {
+        let bytes = text.as_bytes();
+        if bytes.len() != 4
+            || !bytes
+                .iter()
+                .all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        {
+            return None;
+        }
+
+        let mut pattern = OpcodePattern {
+            mask: 0,
+            value: 0,
+            text: [0; 4],
+        };
+        for (i, &byte) in bytes.iter().enumerate() {
+            let shift = 12 - 4 * i;
+            if let Some(digit) = (byte as char).to_digit(16) {
+                pattern.mask |= 0xF << shift;
+                pattern.value |= (digit as u16) << shift;
+            }
+            pattern.text[i] = byte.to_ascii_uppercase();
+        }
+        Some(pattern)
+    }
+
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stop {
+    Address(usize),
+    Opcode(OpcodePattern),
+    Memory { addr: usize, last: u8 },
+    Register { register: usize, last: u8 },
+}
+
+impl Stop {
+    fn describe(&self) -> String {
+        match self {
+            Stop::Address(addr) => format!("break at {:#06X}", addr),
+            Stop::Opcode(pattern) => {
+                format!("break on opcode {}", String::from_utf8_lossy(&pattern.text))
+            }
+            Stop::Memory { addr, .. } => format!("watch memory {:#06X}", addr),
+            Stop::Register { register, .. } => format!("watch V{:X}", register),
+        }
+    }
+}
+
+pub struct Debugger {
+    state: RunState,
+    stops: Vec<Stop>,
+    resume_from: Option<usize>, //Don't break again on the breakpoint we are resuming from
+    stop_reason: Option<String>,
+}
+
+const HELP: &str = "\
+commands:
+  pause | p              pause execution
+  continue | c           resume execution
+  step | s [count]       run count instructions (default 1)
+  frame | f              run to the end of the next frame
+  break | b <addr>       break when the PC reaches addr (hex)
+  opbreak | ob <opcode>  break before an opcode matching a pattern like DXYN or 00E0
+  watch | w <addr|vX>    break when a memory address or V register changes
+  list | l               list breakpoints and watchpoints
+  delete | d <index>     remove a breakpoint or watchpoint
+  regs | r               show registers
+  mem | m <addr> [len]   dump memory
+  help | h               show this message";
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            state: RunState::Running,
+            stops: Vec::new(),
+            resume_from: None,
+            stop_reason: None,
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.state == RunState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = RunState::Paused;
+    }
+
+    pub fn resume(&mut self, chip8: &ChipEight) {
+        self.resume_from = Some(chip8.pc());
+        self.state = RunState::Running;
+    }
+
+    //Why execution last stopped.  Cleared once taken
+    pub fn take_stop_reason(&mut self) -> Option<String> {
+        self.stop_reason.take()
+    }
+
+    //Returns false if the next instruction must not run because the debugger is paused or a breakpoint was hit
+    pub fn before_instruction(&mut self, chip8: &ChipEight) -> bool {
+        match self.state {
+            RunState::Paused => return false,
+            RunState::Stepping(_) => return true,
+            RunState::Running | RunState::FrameStep => {}
+        }
+
+        let pc = chip8.pc();
+        if self.resume_from.take() == Some(pc) {
+            return true;
+        }
+
+        let opcode = chip8.next_opcode();
+        let hit = self.stops.iter().find(|stop| match stop {
+            Stop::Address(addr) => *addr == pc,
+            Stop::Opcode(pattern) => opcode.is_some_and(|opcode| pattern.matches(opcode)),
+            _ => false,
+        });
+        if let Some(stop) = hit {
+            let reason = format!("{}\n{}", stop.describe(), Self::registers(chip8));
+            self.stop(reason);
+            return false;
+        }
+        true
+    }
+
+    pub fn after_instruction(&mut self, chip8: &ChipEight) {
+        let mut changed = Vec::new();
+        for stop in self.stops.iter_mut() {
+            match stop {
+                Stop::Memory { addr, last } => {
+                    let value = chip8.memory()[*addr];
+                    if value != *last {
+                        changed.push(format!(
+                            "memory {:#06X}: {:02X} -> {:02X}",
+                            addr, last, value
+                        ));
+                        *last = value;
+                    }
+                }
+                Stop::Register { register, last } => {
+                    let value = chip8.v_registers()[*register];
+                    if value != *last {
+                        changed.push(format!("V{:X}: {:02X} -> {:02X}", register, last, value));
+                        *last = value;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !changed.is_empty() {
+            let reason = format!("{}\n{}", changed.join("\n"), Self::registers(chip8));
+            self.stop(reason);
+            return;
+        }
+
+        if let RunState::Stepping(remaining) = self.state {
+            if remaining <= 1 {
+                self.stop(Self::registers(chip8));
+            } else {
+                self.state = RunState::Stepping(remaining - 1);
+            }
+        }
+    }
+
+    //Called once a frame has run all its instructions
+    pub fn end_frame(&mut self, chip8: &ChipEight) {
+        if self.state == RunState::FrameStep {
+            self.stop(Self::registers(chip8));
+        }
+    }
+
+    fn stop(&mut self, reason: String) {
+        self.state = RunState::Paused;
+        self.stop_reason = Some(reason);
+    }
+
+    //Runs one command line and returns the text to show the user
+    pub fn execute(&mut self, line: &str, chip8: &ChipEight) -> String {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) => command.to_ascii_lowercase(),
+            None => return String::new(),
+        };
+        let argument = words.next();
+
+        match command.as_str() {
+            "pause" | "p" => {
+                self.pause();
+                Self::registers(chip8)
+            }
+            "continue" | "c" => {
+                self.resume(chip8);
+                "running".to_string()
+            }
+            "step" | "s" => match argument.map_or(Ok(1), |count| count.parse::<usize>()) {
+                Ok(count) if count > 0 => {
+                    self.state = RunState::Stepping(count);
+                    String::new()
+                }
+                _ => "step count must be a positive number".to_string(),
+            },
+            "frame" | "f" => {
+                self.resume_from = Some(chip8.pc());
+                self.state = RunState::FrameStep;
+                String::new()
+            }
+            "break" | "b" => match argument.and_then(parse_address) {
+                Some(addr) => self.add_stop(Stop::Address(addr)),
+                None => "usage: break <addr>".to_string(),
+            },
+            "opbreak" | "ob" => match argument.and_then(OpcodePattern::parse) {
+                Some(pattern) => self.add_stop(Stop::Opcode(pattern)),
+                None => "usage: opbreak <opcode pattern, e.g. DXYN>".to_string(),
+            },
+            "watch" | "w" => match argument {
+                Some(target) if target.starts_with('v') || target.starts_with('V') => {
+                    match usize::from_str_radix(&target[1..], 16) {
+                        Ok(register) if register < 16 => self.add_stop(Stop::Register {
+                            register,
+                            last: chip8.v_registers()[register],
+                        }),
+                        _ => "registers are V0 to VF".to_string(),
+                    }
+                }
+                Some(target) => match parse_address(target) {
+                    Some(addr) if addr < chip8.memory().len() => self.add_stop(Stop::Memory {
+                        addr,
+                        last: chip8.memory()[addr],
+                    }),
+                    _ => "usage: watch <addr|vX>".to_string(),
+                },
+                None => "usage: watch <addr|vX>".to_string(),
+            },
+            "list" | "l" => {
+                if self.stops.is_empty() {
+                    return "no breakpoints or watchpoints".to_string();
+                }
+                self.stops
+                    .iter()
+                    .enumerate()
+                    .map(|(index, stop)| format!("{}: {}", index, stop.describe()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            "delete" | "d" => match argument.and_then(|index| index.parse::<usize>().ok()) {
+                Some(index) if index < self.stops.len() => {
+                    format!("deleted {}", self.stops.remove(index).describe())
+                }
+                _ => "usage: delete <index from list>".to_string(),
+            },
+            "regs" | "r" => Self::registers(chip8),
+            "mem" | "m" => {
+                let addr = match argument.and_then(parse_address) {
+                    Some(addr) => addr,
+                    None => return "usage: mem <addr> [len]".to_string(),
+                };
+                let len = words.next().and_then(|len| len.parse().ok()).unwrap_or(16);
+                Self::dump_memory(chip8, addr, len)
+            }
+            "help" | "h" => HELP.to_string(),
+            _ => format!("unknown command '{}'. Type help for a list", command),
+        }
+    }
+
+    fn add_stop(&mut self, stop: Stop) -> String {
+        self.stops.push(stop);
+        format!("{}: {}", self.stops.len() - 1, stop.describe())
+    }
+
+    pub fn registers(chip8: &ChipEight) -> String {
+        let opcode = chip8
+            .next_opcode()
+            .map_or("----".to_string(), |opcode| format!("{:04X}", opcode));
+        let v = chip8
+            .v_registers()
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("V{:X}={:02X}", i, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let stack = chip8
+            .stack()
+            .iter()
+            .map(|addr| format!("{:03X}", addr))
+            .collect::<Vec<_>>()
+            .join(" ");
+        format!(
+            "PC={:04X} [{}] I={:04X} DT={:02X} ST={:02X}\n{}\nstack: [{}]",
+            chip8.pc(),
+            opcode,
+            chip8.i_register(),
+            chip8.delay_timer(),
+            chip8.sound_timer(),
+            v,
+            stack
+        )
+    }
+
+    fn dump_memory(chip8: &ChipEight, addr: usize, len: usize) -> String {
+        let memory = chip8.memory();
+        let end = addr.saturating_add(len).min(memory.len());
+        (addr..end)
+            .step_by(16)
+            .map(|row| {
+                let bytes = memory[row..row.saturating_add(16).min(end)]
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{:04X}: {}", row, bytes)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+//Addresses are hex with or without a 0x prefix
+fn parse_address(text: &str) -> Option<usize> {
+    let digits = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .unwrap_or(text);
+    usize::from_str_radix(digits, 16).ok()
+}
+
+//Runs the instructions of each frame under the debugger.  A frame the debugger stops partway through picks up
+//where it left off, so every frame runs exactly instructions_per_frame instructions with the keys it started
+//with, however often it is paused.  Keys pressed while it is held wait for the next frame
+pub struct FrameRunner {
+    instructions_per_frame: u32,
+    ran: u32,         //Instructions of the current frame already run
+    keys: [bool; 16], //Keys the current frame started with
+}
+
+impl FrameRunner {
+    pub fn new(instructions_per_frame: u32) -> Self {
+        FrameRunner {
+            instructions_per_frame,
+            ran: 0,
+            keys: [false; 16],
+        }
+    }
+
+    //True while a frame has started but not finished.  Movie input is only applied and recorded between frames
+    pub fn mid_frame(&self) -> bool {
+        self.ran > 0
+    }
+
+    //Forgets a partly run frame, for when the machine is put back to another point in time
+    pub fn reset(&mut self) {
+        self.ran = 0;
+    }
+
+    //Runs the rest of the current frame until the debugger holds an instruction back or the ROM exits.
+    //Returns true once the frame has run all its instructions and the timers should tick
+    pub fn run(
+        &mut self,
+        debugger: &mut Debugger,
+        chip8: &mut ChipEight,
+    ) -> Result<bool, ChipError> {
+        let held = chip8.key;
+        if self.ran == 0 {
+            self.keys = held;
+        }
+        chip8.key = self.keys;
+        let result = self.run_instructions(debugger, chip8);
+        chip8.key = held;
+
+        if result? {
+            self.ran = 0;
+            debugger.end_frame(chip8);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn run_instructions(
+        &mut self,
+        debugger: &mut Debugger,
+        chip8: &mut ChipEight,
+    ) -> Result<bool, ChipError> {
+        while self.ran < self.instructions_per_frame {
+            if !debugger.before_instruction(chip8) {
+                return Ok(false);
+            }
+            let outcome = chip8.emulation_cycle()?;
+            self.ran += 1;
+            debugger.after_instruction(chip8);
+            if outcome == StepOutcome::Exited {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_eight::tests::machine;
+    use crate::headless;
+    use crate::movie::Movie;
+
+    //Runs instructions the way the frontend does until the debugger holds one back.  Returns how many ran
+    fn run(debugger: &mut Debugger, chip8: &mut ChipEight, limit: usize) -> usize {
+        for ran in 0..limit {
+            if !debugger.before_instruction(chip8) {
+                return ran;
+            }
+            chip8.emulation_cycle().unwrap();
+            debugger.after_instruction(chip8);
+        }
+        limit
+    }
+
+    #[test]
+    fn opcode_patterns_match_hex_digits_and_wildcards() {
+        let draw = OpcodePattern::parse("DXYN").unwrap();
+        assert!(draw.matches(0xD123));
+        assert!(!draw.matches(0xC123));
+
+        let add = OpcodePattern::parse("8xy4").unwrap();
+        assert!(add.matches(0x8AB4));
+        assert!(!add.matches(0x8AB5));
+
+        let clear = OpcodePattern::parse("00E0").unwrap();
+        assert!(clear.matches(0x00E0));
+        assert!(!clear.matches(0x00EE));
+
+        assert_eq!(OpcodePattern::parse("DXY"), None);
+        assert_eq!(OpcodePattern::parse("D-YN"), None);
+    }
+
+    #[test]
+    fn breakpoints_stop_and_resume_runs_past_them() {
+        let mut chip8 = machine(&[0x6001, 0x6102, 0x6203, 0x6304]);
+        let mut debugger = Debugger::new();
+        debugger.execute("break 202", &chip8);
+
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 1);
+        assert!(debugger.paused());
+        assert!(debugger
+            .take_stop_reason()
+            .unwrap()
+            .starts_with("break at 0x0202"));
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 0);
+
+        //Resuming runs the instruction at the breakpoint instead of stopping on it again
+        debugger.resume(&chip8);
+        assert_eq!(run(&mut debugger, &mut chip8, 3), 3);
+        assert_eq!(chip8.v_registers()[3], 4);
+    }
+
+    #[test]
+    fn opcode_breakpoints_stop_before_the_opcode() {
+        let mut chip8 = machine(&[0x6001, 0x00E0, 0x6102]);
+        let mut debugger = Debugger::new();
+        debugger.execute("ob 00e0", &chip8);
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 1);
+        assert_eq!(chip8.pc(), 0x202);
+    }
+
+    #[test]
+    fn stepping_runs_the_requested_count() {
+        let mut chip8 = machine(&[0x6001, 0x6102, 0x6203, 0x6304]);
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 0);
+
+        debugger.execute("step 3", &chip8);
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 3);
+        assert!(debugger.paused());
+        debugger.execute("s", &chip8);
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 1);
+        assert_eq!(
+            debugger.execute("step 0", &chip8),
+            "step count must be a positive number"
+        );
+    }
+
+    #[test]
+    fn watchpoints_stop_after_a_change() {
+        let mut chip8 = machine(&[0x6000, 0x6102, 0xA300, 0x6005, 0xF055]);
+        let mut debugger = Debugger::new();
+        debugger.execute("watch v1", &chip8);
+        debugger.execute("watch 300", &chip8);
+
+        //6000 leaves V1 alone, 6102 changes it
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 2);
+        assert!(debugger
+            .take_stop_reason()
+            .unwrap()
+            .starts_with("V1: 00 -> 02"));
+
+        debugger.resume(&chip8);
+        assert_eq!(run(&mut debugger, &mut chip8, 10), 3);
+        assert!(debugger
+            .take_stop_reason()
+            .unwrap()
+            .starts_with("memory 0x0300: 00 -> 05"));
+    }
+
+    #[test]
+    fn frame_steps_stop_at_the_end_of_the_frame() {
+        let chip8 = machine(&[]);
+        let mut debugger = Debugger::new();
+        debugger.end_frame(&chip8);
+        assert!(!debugger.paused());
+
+        debugger.execute("frame", &chip8);
+        debugger.end_frame(&chip8);
+        assert!(debugger.paused());
+    }
+
+    #[test]
+    fn frames_resumed_mid_frame_replay_the_same() {
+        //Counts instructions in V1 and, while key 0 is held, frames in V2
+        let program = [0x7101, 0xE09E, 0x1200, 0x7201, 0x1200];
+        let mut chip8 = machine(&program);
+        let mut movie = Movie::new(&chip8, 10);
+        let mut debugger = Debugger::new();
+        let mut runner = FrameRunner::new(10);
+
+        debugger.execute("step 4", &chip8);
+        movie.record(0, &chip8.key);
+        assert!(!runner.run(&mut debugger, &mut chip8).unwrap());
+        assert!(runner.mid_frame());
+        assert_eq!(chip8.v_registers()[1], 2);
+
+        //Pressed while the frame is held, so it only counts from the next frame
+        chip8.key[0] = true;
+        debugger.resume(&chip8);
+        assert!(runner.run(&mut debugger, &mut chip8).unwrap());
+        assert_eq!(chip8.v_registers()[2], 0);
+        chip8.tick_timers();
+        for frame in 1..4 {
+            assert!(!runner.mid_frame());
+            movie.record(frame, &chip8.key);
+            assert!(runner.run(&mut debugger, &mut chip8).unwrap());
+            chip8.tick_timers();
+        }
+        movie.length = 4;
+
+        let mut replay = machine(&program);
+        movie.start_playback(&mut replay).unwrap();
+        let frames = headless::run(&mut replay, Some(4), 10, &movie.changes).unwrap();
+        assert_eq!(frames, 4);
+        assert_eq!(replay.save_state(), chip8.save_state());
+    }
+
+    #[test]
+    fn memory_dumps_clamp_huge_ranges() {
+        let chip8 = machine(&[0x1234]);
+        assert_eq!(debugger_dump(&chip8, "mem 200 2"), "0200: 12 34");
+        assert_eq!(debugger_dump(&chip8, "mem ffffffffffffffff 16"), "");
+        assert_eq!(
+            debugger_dump(&chip8, "mem fffe 18446744073709551615"),
+            "FFFE: 00 00"
+        );
+    }
+
+    fn debugger_dump(chip8: &ChipEight, command: &str) -> String {
+        Debugger::new().execute(command, chip8)
+    }
+}
@@ -0,0 +1,407 @@
+use crate::chip_eight::{Opcodes, PROGRAM_START_ADDR};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+//Turns a ROM back into readable instructions.  Code is found by following jumps, calls and skips from the
+//entry point so sprites and other data stored between routines are listed as bytes rather than mis-decoded.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    Classic, //Cowgod style: LD V1, 0x20
+    Octo,    //Octo style: v1 := 0x20
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineKind {
+    Code,
+    Data,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+    pub text: String,
+}
+
+//addr  raw bytes  mnemonic
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:<23} {}", self.addr, bytes, self.text)
+    }
+}
+
+const DATA_BYTES_PER_LINE: usize = 8;
+
+//F000 NNNN is the only four byte instruction
+fn instruction_length(opcode: u16) -> usize {
+    if opcode == 0xF000 {
+        4
+    } else {
+        2
+    }
+}
+
+fn opcode_at(rom: &[u8], offset: usize) -> Option<u16> {
+    let bytes = rom.get(offset..offset + 2)?;
+    Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+}
+
+//Addresses where instructions start, found by tracing every path the program can take from the entry point
+fn find_code(rom: &[u8]) -> BTreeSet<usize> {
+    let end = PROGRAM_START_ADDR + rom.len();
+    let mut code = BTreeSet::new();
+    let mut pending = vec![PROGRAM_START_ADDR];
+
+    while let Some(addr) = pending.pop() {
+        if addr < PROGRAM_START_ADDR || code.contains(&addr) {
+            continue;
+        }
+        //Paths that run into something that isn't a whole instruction are treated as ending there
+        let opcode = match opcode_at(rom, addr - PROGRAM_START_ADDR) {
+            Some(opcode)
+                if addr + instruction_length(opcode) <= end
+                    && mnemonic(opcode, 0, Syntax::Classic).is_some() =>
+            {
+                opcode
+            }
+            _ => continue,
+        };
+        code.insert(addr);
+
+        let next = addr + instruction_length(opcode);
+        match Opcodes::decode(opcode) {
+            Opcodes::Jump(target) => pending.push(target),
+            Opcodes::Call(target) => {
+                pending.push(target);
+                pending.push(next);
+            }
+            //Skips continue at either of the next two instructions
+            Opcodes::SkipEqual(..)
+            | Opcodes::SkipNotEqual(..)
+            | Opcodes::SkipEqualVy(..)
+            | Opcodes::SkipNotEqualVy(..)
+            | Opcodes::SkipPressed(..) => {
+                pending.push(next);
+                if let Some(skipped) = opcode_at(rom, next - PROGRAM_START_ADDR) {
+                    pending.push(next + instruction_length(skipped));
+                }
+            }
+            //Return and exit end the path.  BNNN depends on a register so its target can't be known
+            Opcodes::ClearOrReturn(0xEE) | Opcodes::ClearOrReturn(0xFD) => {}
+            Opcodes::JumpOffset(_) => {}
+            _ => pending.push(next),
+        }
+    }
+
+    code
+}
+
+pub fn disassemble(rom: &[u8], syntax: Syntax) -> Vec<Line> {
+    let code = find_code(rom);
+    let end = PROGRAM_START_ADDR + rom.len();
+    let mut lines = Vec::new();
+    let mut addr = PROGRAM_START_ADDR;
+
+    while addr < end {
+        let offset = addr - PROGRAM_START_ADDR;
+        if code.contains(&addr) {
+            let opcode = opcode_at(rom, offset).unwrap_or(0);
+            let length = instruction_length(opcode);
+            let operand = opcode_at(rom, offset + 2).unwrap_or(0);
+            lines.push(Line {
+                addr,
+                bytes: rom[offset..offset + length].to_vec(),
+                kind: LineKind::Code,
+                text: mnemonic(opcode, operand, syntax).unwrap_or_default(),
+            });
+            addr += length;
+        } else {
+            //Group data up to the next instruction
+            let mut length = 1;
+            while length < DATA_BYTES_PER_LINE
+                && addr + length < end
+                && !code.contains(&(addr + length))
+            {
+                length += 1;
+            }
+            let bytes = rom[offset..offset + length].to_vec();
+            lines.push(Line {
+                addr,
+                text: data(&bytes, syntax),
+                bytes,
+                kind: LineKind::Data,
+            });
+            addr += length;
+        }
+    }
+
+    lines
+}
+
+//Just the mnemonics, one per line.  Classic syntax output can be fed straight back into the assembler
+pub fn source(rom: &[u8], syntax: Syntax) -> String {
+    disassemble(rom, syntax)
+        .iter()
+        .map(|line| format!("{}\n", line.text))
+        .collect()
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    let values = bytes
+        .iter()
+        .map(|byte| format!("0x{:02X}", byte))
+        .collect::<Vec<_>>();
+    match syntax {
+        Syntax::Classic => format!("DB {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+//operand is the word following the opcode.  Only used by the four byte F000 NNNN.
+//Returns None for opcodes the interpreter can't execute
+pub fn mnemonic(opcode: u16, operand: u16, syntax: Syntax) -> Option<String> {
+    match syntax {
+        Syntax::Classic => classic(opcode, operand),
+        Syntax::Octo => octo(opcode, operand),
+    }
+}
+
+fn classic(opcode: u16, operand: u16) -> Option<String> {
+    let n = opcode & 0xF;
+    Some(match Opcodes::decode(opcode) {
+        Opcodes::ClearOrReturn(sub) if opcode <= 0xFF => match sub {
+            0xE0 => "CLS".to_string(),
+            0xEE => "RET".to_string(),
+            0xC0..=0xCF => format!("SCD {}", n),
+            0xD0..=0xDF => format!("SCU {}", n),
+            0xFB => "SCR".to_string(),
+            0xFC => "SCL".to_string(),
+            0xFD => "EXIT".to_string(),
+            0xFE => "LOW".to_string(),
+            0xFF => "HIGH".to_string(),
+            _ => return None, //0NNN machine code calls aren't supported
+        },
+        Opcodes::Jump(addr) => format!("JP 0x{:03X}", addr),
+        Opcodes::Call(addr) => format!("CALL 0x{:03X}", addr),
+        Opcodes::SkipEqual(x, k) => format!("SE V{:X}, 0x{:02X}", x, k),
+        Opcodes::SkipNotEqual(x, k) => format!("SNE V{:X}, 0x{:02X}", x, k),
+        Opcodes::SkipEqualVy(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Opcodes::SaveRange(x, y) => format!("SAVE V{:X}, V{:X}", x, y),
+        Opcodes::LoadRange(x, y) => format!("LOAD V{:X}, V{:X}", x, y),
+        Opcodes::LoadVxK(x, k) => format!("LD V{:X}, 0x{:02X}", x, k),
+        Opcodes::AddByte(x, k) => format!("ADD V{:X}, 0x{:02X}", x, k),
+        Opcodes::Arithmetic(sub, x, y) => match sub {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => return None,
+        },
+        Opcodes::SkipNotEqualVy(x, y) if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        Opcodes::LoadI(addr) => format!("LD I, 0x{:03X}", addr),
+        Opcodes::JumpOffset(addr) => format!("JP V0, 0x{:03X}", addr),
+        Opcodes::RandomVxByte(x, k) => format!("RND V{:X}, 0x{:02X}", x, k),
+        Opcodes::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Opcodes::SkipPressed(x, _) => match opcode & 0xFF {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => return None,
+        },
+        Opcodes::Misc(x, sub) => match sub {
+            0x00 if x == 0 => format!("LD I, LONG 0x{:04X}", operand),
+            0x01 => format!("PLANE {}", x),
+            0x02 if x == 0 => "AUDIO".to_string(),
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x3A => format!("PITCH V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => return None,
+        },
+        //decode only reads the fields each instruction uses, so undocumented encodings like 01E0 and 9XY1
+        //land on the same variants as 00E0 and 9XY0.  They are data so the listing reassembles to the same bytes
+        Opcodes::ClearOrReturn(_) | Opcodes::SkipNotEqualVy(..) | Opcodes::BadOpcode => {
+            return None
+        }
+    })
+}
+
+fn octo(opcode: u16, operand: u16) -> Option<String> {
+    let n = opcode & 0xF;
+    Some(match Opcodes::decode(opcode) {
+        Opcodes::ClearOrReturn(sub) if opcode <= 0xFF => match sub {
+            0xE0 => "clear".to_string(),
+            0xEE => "return".to_string(),
+            0xC0..=0xCF => format!("scroll-down {}", n),
+            0xD0..=0xDF => format!("scroll-up {}", n),
+            0xFB => "scroll-right".to_string(),
+            0xFC => "scroll-left".to_string(),
+            0xFD => "exit".to_string(),
+            0xFE => "lores".to_string(),
+            0xFF => "hires".to_string(),
+            _ => return None, //0NNN machine code calls aren't supported
+        },
+        Opcodes::Jump(addr) => format!("jump 0x{:03X}", addr),
+        Opcodes::Call(addr) => format!(":call 0x{:03X}", addr),
+        //Octo writes skips as the condition under which the next instruction runs
+        Opcodes::SkipEqual(x, k) => format!("if v{:x} != 0x{:02X} then", x, k),
+        Opcodes::SkipNotEqual(x, k) => format!("if v{:x} == 0x{:02X} then", x, k),
+        Opcodes::SkipEqualVy(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Opcodes::SaveRange(x, y) => format!("save v{:x} - v{:x}", x, y),
+        Opcodes::LoadRange(x, y) => format!("load v{:x} - v{:x}", x, y),
+        Opcodes::LoadVxK(x, k) => format!("v{:x} := 0x{:02X}", x, k),
+        Opcodes::AddByte(x, k) => format!("v{:x} += 0x{:02X}", x, k),
+        Opcodes::Arithmetic(sub, x, y) => match sub {
+            0x0 => format!("v{:x} := v{:x}", x, y),
+            0x1 => format!("v{:x} |= v{:x}", x, y),
+            0x2 => format!("v{:x} &= v{:x}", x, y),
+            0x3 => format!("v{:x} ^= v{:x}", x, y),
+            0x4 => format!("v{:x} += v{:x}", x, y),
+            0x5 => format!("v{:x} -= v{:x}", x, y),
+            0x6 => format!("v{:x} >>= v{:x}", x, y),
+            0x7 => format!("v{:x} =- v{:x}", x, y),
+            0xE => format!("v{:x} <<= v{:x}", x, y),
+            _ => return None,
+        },
+        Opcodes::SkipNotEqualVy(x, y) if n == 0 => format!("if v{:x} == v{:x} then", x, y),
+        Opcodes::LoadI(addr) => format!("i := 0x{:03X}", addr),
+        Opcodes::JumpOffset(addr) => format!("jump0 0x{:03X}", addr),
+        Opcodes::RandomVxByte(x, k) => format!("v{:x} := random 0x{:02X}", x, k),
+        Opcodes::Draw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Opcodes::SkipPressed(x, _) => match opcode & 0xFF {
+            0x9E => format!("if v{:x} -key then", x),
+            0xA1 => format!("if v{:x} key then", x),
+            _ => return None,
+        },
+        Opcodes::Misc(x, sub) => match sub {
+            0x00 if x == 0 => format!("i := long 0x{:04X}", operand),
+            0x01 => format!("plane {}", x),
+            0x02 if x == 0 => "audio".to_string(),
+            0x07 => format!("v{:x} := delay", x),
+            0x0A => format!("v{:x} := key", x),
+            0x15 => format!("delay := v{:x}", x),
+            0x18 => format!("buzzer := v{:x}", x),
+            0x1E => format!("i += v{:x}", x),
+            0x29 => format!("i := hex v{:x}", x),
+            0x30 => format!("i := bighex v{:x}", x),
+            0x33 => format!("bcd v{:x}", x),
+            0x3A => format!("pitch := v{:x}", x),
+            0x55 => format!("save v{:x}", x),
+            0x65 => format!("load v{:x}", x),
+            0x75 => format!("saveflags v{:x}", x),
+            0x85 => format!("loadflags v{:x}", x),
+            _ => return None,
+        },
+        //decode only reads the fields each instruction uses, so undocumented encodings like 01E0 and 9XY1
+        //land on the same variants as 00E0 and 9XY0.  They are data so the listing reassembles to the same bytes
+        Opcodes::ClearOrReturn(_) | Opcodes::SkipNotEqualVy(..) | Opcodes::BadOpcode => {
+            return None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(words: &[u16]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+
+    fn listing(rom: &[u8]) -> Vec<(usize, LineKind, String)> {
+        disassemble(rom, Syntax::Classic)
+            .into_iter()
+            .map(|line| (line.addr, line.kind, line.text))
+            .collect()
+    }
+
+    #[test]
+    fn data_between_routines_is_not_decoded() {
+        //Jumps over a two byte sprite, then calls a subroutine placed after it
+        let rom = rom(&[0x1204, 0xFF81, 0x2208, 0x1206, 0x00EE]);
+        assert_eq!(
+            listing(&rom),
+            vec![
+                (0x200, LineKind::Code, "JP 0x204".to_string()),
+                (0x202, LineKind::Data, "DB 0xFF, 0x81".to_string()),
+                (0x204, LineKind::Code, "CALL 0x208".to_string()),
+                (0x206, LineKind::Code, "JP 0x206".to_string()),
+                (0x208, LineKind::Code, "RET".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_follow_both_paths() {
+        let rom = rom(&[0x3001, 0x1206, 0x00FD, 0x00E0]);
+        let kinds: Vec<LineKind> = listing(&rom).into_iter().map(|line| line.1).collect();
+        assert_eq!(kinds, vec![LineKind::Code; 4]);
+    }
+
+    #[test]
+    fn jump_tables_after_bnnn_fall_back_to_data() {
+        //BNNN's target depends on V0, so the table of jumps after it can't be traced
+        let rom = rom(&[0x6002, 0xB204, 0x1208, 0x120A]);
+        let lines = listing(&rom);
+        assert_eq!(
+            lines[1],
+            (0x202, LineKind::Code, "JP V0, 0x204".to_string())
+        );
+        assert_eq!(lines[2].1, LineKind::Data);
+        assert_eq!(lines[2].2, "DB 0x12, 0x08, 0x12, 0x0A");
+    }
+
+    #[test]
+    fn undocumented_encodings_are_data() {
+        let rom = rom(&[0x9121, 0x01E0]);
+        assert!(listing(&rom).iter().all(|line| line.1 == LineKind::Data));
+    }
+
+    #[test]
+    fn octo_syntax() {
+        let rom = rom(&[0x6120, 0x8124, 0x3105, 0x00E0, 0xD125, 0xF155, 0x1200]);
+        assert_eq!(
+            source(&rom, Syntax::Octo),
+            "v1 := 0x20\nv1 += v2\nif v1 != 0x05 then\nclear\nsprite v1 v2 5\nsave v1\njump 0x200\n"
+        );
+    }
+
+    #[test]
+    fn long_load_f000_is_four_bytes() {
+        let rom = rom(&[0xF000, 0x1234, 0x00FD]);
+        let lines = disassemble(&rom, Syntax::Classic);
+        assert_eq!(lines[0].bytes, vec![0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(lines[0].text, "LD I, LONG 0x1234");
+        assert_eq!(lines[1].addr, 0x204);
+        assert_eq!(lines[1].text, "EXIT");
+        assert_eq!(
+            mnemonic(0xF000, 0x1234, Syntax::Octo).unwrap(),
+            "i := long 0x1234"
+        );
+
+        //Cut off before its operand it can't be code
+        assert_eq!(listing(&rom[..2])[0].1, LineKind::Data);
+    }
+}
@@ -0,0 +1,62 @@
+use crate::chip_eight::{MEMORY_SIZE, PROGRAM_START_ADDR};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+//Everything that can stop a ROM from running.  Returned to the frontend instead of panicking so it can report the crash
+#[derive(Debug)]
+pub enum ChipError {
+    StackOverflow,
+    StackUnderflow,
+    MemoryOutOfBounds(usize),  //address
+    InvalidOpcode(u16, usize), //opcode, address of the instruction
+    RomTooLarge(usize),        //size of the ROM in bytes
+    InvalidSaveState(&'static str),
+    SaveStateRomMismatch, //The save state was made with a different ROM
+    InvalidMovie(&'static str),
+    MovieRomMismatch, //The movie was recorded with a different ROM
+    Io(io::Error),
+}
+
+impl fmt::Display for ChipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChipError::StackOverflow => write!(f, "stack overflow"),
+            ChipError::StackUnderflow => write!(f, "return with an empty stack"),
+            ChipError::MemoryOutOfBounds(addr) => {
+                write!(f, "memory access out of bounds at {:#06X}", addr)
+            }
+            ChipError::InvalidOpcode(opcode, pc) => {
+                write!(f, "invalid opcode {:04X} at {:#06X}", opcode, pc)
+            }
+            ChipError::RomTooLarge(size) => write!(
+                f,
+                "ROM is too large ({} bytes, at most {} fit in memory)",
+                size,
+                MEMORY_SIZE - PROGRAM_START_ADDR
+            ),
+            ChipError::InvalidSaveState(reason) => write!(f, "invalid save state: {}", reason),
+            ChipError::SaveStateRomMismatch => {
+                write!(f, "save state belongs to a different ROM")
+            }
+            ChipError::InvalidMovie(reason) => write!(f, "invalid movie: {}", reason),
+            ChipError::MovieRomMismatch => write!(f, "movie was recorded with a different ROM"),
+            ChipError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ChipError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChipError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChipError {
+    fn from(e: io::Error) -> Self {
+        ChipError::Io(e)
+    }
+}
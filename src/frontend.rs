@@ -0,0 +1,448 @@
+use crate::cli::Options;
+use crate::sdl_audio::*;
+use crate::sdl_gamepad::SdlGamepads;
+use crate::user_interface::*;
+use chip_eight_emulator::audio::*;
+use chip_eight_emulator::chip_eight::*;
+use chip_eight_emulator::debugger::{Debugger, FrameRunner};
+use chip_eight_emulator::error::ChipError;
+use chip_eight_emulator::gamepad::GamepadMap;
+use chip_eight_emulator::ini;
+use chip_eight_emulator::keymap::Keymap;
+use chip_eight_emulator::movie::Movie;
+use chip_eight_emulator::palette::{self, Palette};
+use chip_eight_emulator::phosphor::Phosphor;
+use chip_eight_emulator::rewind::RewindBuffer;
+use chip_eight_emulator::storage;
+use sdl2::controller::{Axis, Button};
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//Runs a ROM in an SDL window until the user quits.  playback has already been started on my_chip8.
+//Returns an error if SDL couldn't open the window or the ROM crashed
+pub fn run(
+    mut my_chip8: ChipEight,
+    options: &Options,
+    mut playback: Option<Movie>,
+) -> Result<(), String> {
+    let instructions_per_frame = options.instructions_per_frame;
+
+    //Restore the high scores this ROM saved through the RPL user flags last session.  Replays bring their own
+    let data_dir = storage::data_dir();
+    if playback.is_none() {
+        println!(
+            "Random seed {}.  Pass --seed to repeat this run",
+            my_chip8.seed()
+        );
+        match storage::load_rpl_flags(&data_dir, my_chip8.rom_hash()) {
+            Ok(flags) => my_chip8.set_rpl_flags(flags),
+            Err(e) => eprintln!("Could not load RPL flags: {}", e),
+        }
+    }
+    let mut recording = options
+        .record
+        .as_ref()
+        .map(|_| Movie::new(&my_chip8, instructions_per_frame));
+
+    let sdl_context = sdl2::init().map_err(|e| format!("Could not start SDL: {}", e))?;
+    let (keymap, gamepad_map) = load_input_config(options, &my_chip8, &data_dir);
+    let canvas = create_canvas(&sdl_context, options.scale)
+        .map_err(|e| format!("Could not open a window: {}", e))?;
+    let texture_creator = canvas.texture_creator();
+    let mut my_user_interface = UserInterface::new(
+        canvas,
+        &texture_creator,
+        options.palette,
+        keymap,
+        options.phosphor.map(Phosphor::new),
+    )
+    .map_err(|e| format!("Could not open a window: {}", e))?;
+    let controller_db = storage::controller_db_path(&data_dir);
+    let mut gamepads = match SdlGamepads::new(&sdl_context, gamepad_map, &controller_db) {
+        Ok(gamepads) => Some(gamepads),
+        Err(e) => {
+            eprintln!("Controllers unavailable: {}", e);
+            None
+        }
+    };
+    let mut event_pump = sdl_context
+        .event_pump()
+        .map_err(|e| format!("Could not read input events: {}", e))?;
+    let mut audio: Box<dyn AudioBackend> = match SdlAudio::new(&sdl_context, options.audio) {
+        Ok(sdl_audio) => Box::new(sdl_audio),
+        Err(e) => {
+            eprintln!("Audio unavailable, continuing without sound: {}", e);
+            Box::new(NullAudio)
+        }
+    };
+    let mut quit = false;
+    let mut crash = None; //Why the ROM stopped, if it crashed
+    let mut render_failing = false; //The last frame could not be drawn and that has been reported
+
+    //P cycles through the presets, starting from whichever palette --palette chose
+    let mut palettes = vec![options.palette];
+    palettes.extend(
+        palette::PRESETS
+            .iter()
+            .map(|&(_, palette)| palette)
+            .filter(|&palette| palette != options.palette),
+    );
+
+    let mut rewind_buffer = RewindBuffer::new(
+        options
+            .rewind_seconds
+            .saturating_mul(TIMER_FREQUENCY as usize),
+    );
+    let mut rewinding = false; //Backspace is held
+
+    //Debugger commands are typed into the terminal while the window keeps running
+    let mut debugger = Debugger::new();
+    let debugger_commands = spawn_stdin_reader();
+    let mut frame_runner = FrameRunner::new(instructions_per_frame);
+
+    let frame_duration = Duration::from_secs(1) / TIMER_FREQUENCY;
+    let mut next_frame = Instant::now() + frame_duration;
+    //Frames the ROM has run.  Movies are keyed on this, and a frame only counts once all its instructions
+    //have run, so debugger pauses don't shift them
+    let mut frame: u32 = 0;
+
+    while !quit {
+        if rewinding {
+            //Play the game backwards by restoring the previous frame instead of running this one.
+            //Keep the keys that are physically held now rather than the ones held back then
+            if let Some(snapshot) = rewind_buffer.rewind() {
+                let key = my_chip8.key;
+                if let Err(e) = my_chip8.load_state(snapshot) {
+                    eprintln!("Could not rewind: {}", e);
+                }
+                my_chip8.key = key;
+                frame_runner.reset();
+            }
+        } else {
+            //Input only changes between frames.  A frame the debugger stopped partway through keeps its keys
+            if !frame_runner.mid_frame() {
+                if let Some(keys) = playback.as_ref().and_then(|movie| movie.keys_at(frame)) {
+                    my_chip8.key = keys;
+                }
+                if let Some(movie) = recording.as_mut() {
+                    movie.record(frame, &my_chip8.key);
+                }
+            }
+
+            //A frame only ticks the timers and joins the rewind history once all its instructions have run
+            match frame_runner.run(&mut debugger, &mut my_chip8) {
+                Ok(true) => {
+                    my_chip8.tick_timers();
+                    my_user_interface.end_frame(&my_chip8);
+                    rewind_buffer.push(my_chip8.save_state());
+                    frame += 1;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    crash = Some(e);
+                    quit = true;
+                }
+            }
+            if my_chip8.exited() {
+                quit = true;
+            }
+        }
+        //Draw once per frame with whatever the ROM left on the display, instead of after every instruction
+        let display_changed = my_chip8.take_display_dirty();
+        //Only the first of a run of failed draws is reported so the terminal isn't flooded every frame
+        match my_user_interface.render(&my_chip8, display_changed) {
+            Ok(()) => render_failing = false,
+            Err(e) => {
+                if !render_failing {
+                    eprintln!("Could not draw the display: {}", e);
+                }
+                render_failing = true;
+            }
+        }
+
+        if options.frames.is_some_and(|frames| frame >= frames) {
+            quit = true;
+        }
+        if playback.as_ref().is_some_and(|movie| frame >= movie.length) {
+            println!("Replay finished");
+            playback = None;
+        }
+        //Jumping to another point in time would desync the movie from the ROM
+        let movie_active = recording.is_some() || playback.is_some();
+        audio.update(&my_chip8);
+
+        if let Some(reason) = debugger.take_stop_reason() {
+            println!("{}", reason);
+        }
+        while let Ok(command) = debugger_commands.try_recv() {
+            let output = debugger.execute(&command, &my_chip8);
+            if !output.is_empty() {
+                println!("{}", output);
+            }
+        }
+
+        //Only flags the ROM wrote with FX75 are saved.  Rewinding or loading a state can bring back older
+        //flags, and those must not overwrite a newer high score on disk.  Nor may a replay's
+        if my_chip8.take_rpl_flags_dirty() && playback.is_none() {
+            if let Err(e) =
+                storage::save_rpl_flags(&data_dir, my_chip8.rom_hash(), my_chip8.rpl_flags())
+            {
+                eprintln!("Could not save RPL flags: {}", e);
+            }
+        }
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    quit = true;
+                }
+                //Keys the keymap binds go to the keypad even if they are also a hotkey.  The keypad belongs to
+                //the movie until the replay finishes
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    scancode,
+                    repeat,
+                    ..
+                } if my_user_interface.is_keypad_key(keycode, scancode) => {
+                    if playback.is_none() && !repeat {
+                        my_user_interface.key_press(keycode, scancode);
+                    }
+                }
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    scancode,
+                    ..
+                } if my_user_interface.is_keypad_key(keycode, scancode) => {
+                    if playback.is_none() {
+                        my_user_interface.key_release(keycode, scancode);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    ..
+                } => audio.toggle_mute(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    let palette = next_palette(&palettes, my_user_interface.palette());
+                    my_user_interface.set_palette(palette);
+                    println!("Palette: {}", palette.name().unwrap_or("custom"));
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    ..
+                } => {
+                    if debugger.paused() {
+                        debugger.resume(&my_chip8);
+                        println!("running");
+                    } else {
+                        debugger.pause();
+                        println!("{}", Debugger::registers(&my_chip8));
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } if movie_active => {
+                    eprintln!("Rewind is disabled while a movie is recording or playing")
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                //F1-F9 load save state slots 1-9.  Hold shift to save instead
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } if save_state_slot(keycode).is_some() => {
+                    let slot = save_state_slot(keycode).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        save_state(&my_chip8, &data_dir, slot);
+                    } else if movie_active {
+                        eprintln!(
+                            "Loading states is disabled while a movie is recording or playing"
+                        );
+                    } else {
+                        load_state(&mut my_chip8, &data_dir, slot);
+                        frame_runner.reset();
+                    }
+                }
+                event => {
+                    if let Some(gamepads) = gamepads.as_mut() {
+                        gamepads.handle_event(&event, playback.is_none());
+                    }
+                }
+            }
+        }
+
+        //The keyboard and controllers hold keys separately, so letting go of a key on one doesn't release it
+        //while the other still holds it
+        if playback.is_none() {
+            let mut keys = my_user_interface.keys();
+            if let Some(gamepads) = gamepads.as_ref() {
+                for (key, held) in keys.iter_mut().zip(gamepads.keys()) {
+                    *key |= held;
+                }
+            }
+            my_chip8.key = keys;
+        }
+
+        //Sleep until the next frame is due.  If we are already running late start the next frame from now rather than trying to catch up
+        let now = Instant::now();
+        if now < next_frame {
+            ::std::thread::sleep(next_frame - now);
+            next_frame += frame_duration;
+        } else {
+            next_frame = now + frame_duration;
+        }
+    }
+
+    if let (Some(mut movie), Some(path)) = (recording, options.record.as_ref()) {
+        movie.length = frame;
+        match fs::write(path, movie.to_bytes()) {
+            Ok(()) => println!("Recorded {} frames to {}", frame, path),
+            Err(e) => eprintln!("Could not write {}: {}", path, e),
+        }
+    }
+    match crash {
+        Some(e) => Err(format!("ROM crashed: {}", e)),
+        None => Ok(()),
+    }
+}
+
+//Reads lines from the terminal on another thread so the emulator never blocks waiting for input
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+//Reads the keymap and controller profile for this ROM.  Falls back to the default layouts if there is no
+//config or it can't be used
+fn load_input_config(
+    options: &Options,
+    chip8: &ChipEight,
+    data_dir: &Path,
+) -> (Keymap, GamepadMap) {
+    let path = match &options.keymap {
+        Some(path) => PathBuf::from(path),
+        None => storage::keymap_path(data_dir),
+    };
+    let sections = match fs::read_to_string(&path) {
+        Ok(text) => ini::parse(&text),
+        //Only complain about a missing file if it was asked for
+        Err(e) if e.kind() == io::ErrorKind::NotFound && options.keymap.is_none() => Ok(Vec::new()),
+        Err(e) => Err(e.to_string()),
+    };
+    let sections = sections.unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path.display(), e);
+        Vec::new()
+    });
+
+    let rom_name = Path::new(&options.rom_path)
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let keymap = match Keymap::from_config(&sections, &rom_name, chip8.rom_hash()) {
+        Ok(keymap) => {
+            for name in keymap.keycode_names() {
+                if Keycode::from_name(name).is_none() {
+                    eprintln!("{}: unknown key {}", path.display(), name);
+                }
+            }
+            for name in keymap.scancode_names() {
+                if Scancode::from_name(name).is_none() {
+                    eprintln!("{}: unknown scancode {}", path.display(), name);
+                }
+            }
+            keymap
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            Keymap::default()
+        }
+    };
+    let gamepad_map = match GamepadMap::from_config(&sections, &rom_name, chip8.rom_hash()) {
+        Ok(gamepad_map) => {
+            for name in gamepad_map.button_names() {
+                if Button::from_string(name).is_none() {
+                    eprintln!("{}: unknown controller button {}", path.display(), name);
+                }
+            }
+            for name in gamepad_map.axis_names() {
+                if Axis::from_string(name).is_none() {
+                    eprintln!("{}: unknown controller axis {}", path.display(), name);
+                }
+            }
+            gamepad_map
+        }
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            GamepadMap::default()
+        }
+    };
+    (keymap, gamepad_map)
+}
+
+fn next_palette(palettes: &[Palette], current: Palette) -> Palette {
+    let index = palettes.iter().position(|&palette| palette == current);
+    palettes[index.map_or(0, |index| (index + 1) % palettes.len())]
+}
+
+fn save_state_slot(keycode: Keycode) -> Option<u8> {
+    match keycode {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+fn save_state(chip8: &ChipEight, data_dir: &Path, slot: u8) {
+    match storage::write_save_state(data_dir, chip8.rom_hash(), slot, &chip8.save_state()) {
+        Ok(()) => println!("Saved state to slot {}", slot),
+        Err(e) => eprintln!("Could not save state to slot {}: {}", slot, e),
+    }
+}
+
+fn load_state(chip8: &mut ChipEight, data_dir: &Path, slot: u8) {
+    let result = storage::load_save_state(data_dir, chip8.rom_hash(), slot)
+        .map_err(ChipError::from)
+        .and_then(|state| chip8.load_state(&state));
+    match result {
+        Ok(()) => println!("Loaded state from slot {}", slot),
+        Err(e) => eprintln!("Could not load state from slot {}: {}", slot, e),
+    }
+}
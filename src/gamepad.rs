@@ -0,0 +1,173 @@
+use crate::ini;
+
+use std::collections::HashMap;
+
+//Which game controller buttons and stick directions press which hex keypad keys.  Buttons use SDL's game
+//controller names ("a", "start", "dpup", "leftshoulder") and stick directions are an axis name followed by
+//+ or - ("leftx-" is the left stick pushed left, "lefty+" pushed down, "righttrigger+" the trigger pulled).
+//SDL maps every controller it knows onto the same Xbox style layout, so one profile fits all of them.
+//
+//Config file, the same one as the keymap:
+//  [controller]            ; replaces the default layout
+//  dpup = 2
+//  deadzone = 12000        ; how far a stick has to move before it counts, out of 32767
+//  [controller pong.ch8]   ; adds to or overrides [controller] for one ROM, by file name or hash
+//  dpup = 1
+//  dpdown = 4
+
+//Directions on the D-pad and left stick go to 2 4 6 8, which most games use for movement,
+//and the face buttons to the keys games most often use for actions
+const DEFAULT_LAYOUT: [(&str, u8); 14] = [
+    ("dpup", 0x2),
+    ("dpdown", 0x8),
+    ("dpleft", 0x4),
+    ("dpright", 0x6),
+    ("lefty-", 0x2),
+    ("lefty+", 0x8),
+    ("leftx-", 0x4),
+    ("leftx+", 0x6),
+    ("a", 0x5),
+    ("b", 0x0),
+    ("x", 0xA),
+    ("y", 0xB),
+    ("back", 0xE),
+    ("start", 0xF),
+];
+
+pub const DEFAULT_DEADZONE: i16 = 12_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GamepadMap {
+    buttons: HashMap<String, u8>, //lower case button name -> keypad key
+    axes: HashMap<String, u8>,    //lower case axis name with its + or - -> keypad key
+    pub deadzone: i16,
+}
+
+impl Default for GamepadMap {
+    fn default() -> Self {
+        let mut map = GamepadMap::empty();
+        for &(name, key) in DEFAULT_LAYOUT.iter() {
+            map.insert(name, key);
+        }
+        map
+    }
+}
+
+impl GamepadMap {
+    fn empty() -> Self {
+        GamepadMap {
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+            deadzone: DEFAULT_DEADZONE,
+        }
+    }
+
+    fn insert(&mut self, name: &str, key: u8) {
+        if name.ends_with('+') || name.ends_with('-') {
+            self.axes.insert(name.to_string(), key);
+        } else {
+            self.buttons.insert(name.to_string(), key);
+        }
+    }
+
+    //Builds the controller profile for one ROM from the parsed config file
+    pub fn from_config(
+        sections: &[ini::Section],
+        rom_name: &str,
+        rom_hash: u64,
+    ) -> Result<Self, String> {
+        let sections = ini::sections_for(sections, "controller", rom_name, rom_hash);
+
+        let mut map = if sections.iter().any(|section| section.name == "controller") {
+            GamepadMap::empty()
+        } else {
+            GamepadMap::default()
+        };
+        for entry in sections.iter().flat_map(|section| section.entries.iter()) {
+            let name = entry.name.to_ascii_lowercase();
+            if name == "deadzone" {
+                map.deadzone = match entry.value.parse() {
+                    Ok(deadzone) if deadzone >= 0 => deadzone,
+                    _ => {
+                        return Err(format!(
+                            "line {}: deadzone must be 0-32767, found '{}'",
+                            entry.line, entry.value
+                        ))
+                    }
+                };
+            } else {
+                map.insert(&name, entry.keypad_key()?);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn button(&self, name: &str) -> Option<u8> {
+        self.buttons.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    //The key for an axis position, or None while the stick is inside the deadzone
+    pub fn axis(&self, name: &str, value: i16) -> Option<u8> {
+        let direction = if value > self.deadzone {
+            '+'
+        } else if value < -self.deadzone {
+            '-'
+        } else {
+            return None;
+        };
+        let name = format!("{}{}", name.to_ascii_lowercase(), direction);
+        self.axes.get(&name).copied()
+    }
+
+    //Names for the frontend to check against the buttons and axes it knows
+    pub fn button_names(&self) -> impl Iterator<Item = &str> {
+        self.buttons.keys().map(String::as_str)
+    }
+
+    //Axis names without their direction
+    pub fn axis_names(&self) -> impl Iterator<Item = &str> {
+        self.axes.keys().map(|name| &name[..name.len() - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_config(config: &str, rom_name: &str, rom_hash: u64) -> Result<GamepadMap, String> {
+        GamepadMap::from_config(&ini::parse(config).unwrap(), rom_name, rom_hash)
+    }
+
+    #[test]
+    fn default_moves_with_the_dpad_and_left_stick() {
+        let map = GamepadMap::default();
+        assert_eq!(map.button("dpup"), Some(0x2));
+        assert_eq!(map.button("A"), Some(0x5));
+        assert_eq!(map.axis("leftx", -20_000), Some(0x4));
+        assert_eq!(map.axis("leftx", 20_000), Some(0x6));
+        assert_eq!(map.axis("leftx", 5_000), None);
+        assert_eq!(map.axis("rightx", 20_000), None);
+    }
+
+    #[test]
+    fn controller_sections_replace_the_default_per_rom() {
+        let config = "[keys]\nQ = 1\n[controller]\na = 1\nrighttrigger+ = C\ndeadzone = 100\n\
+                      [controller pong.ch8]\ndpup = 1\ndpdown = 4\n";
+        let pong = from_config(config, "pong.ch8", 0).unwrap();
+        assert_eq!(pong.button("dpup"), Some(0x1));
+        assert_eq!(pong.button("dpleft"), None);
+        assert_eq!(pong.axis("righttrigger", 200), Some(0xC));
+        assert_eq!(pong.deadzone, 100);
+
+        let other = from_config(config, "tetris.ch8", 0).unwrap();
+        assert_eq!(other.button("dpup"), None);
+        assert_eq!(other.button("a"), Some(0x1));
+    }
+
+    #[test]
+    fn bad_entries_report_their_line() {
+        let error = from_config("[controller]\n\na = G\n", "", 0).unwrap_err();
+        assert!(error.starts_with("line 3:"));
+        assert!(from_config("[controller]\ndeadzone = -1\n", "", 0).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+use crate::chip_eight::{ChipEight, StepOutcome};
+use crate::error::ChipError;
+use crate::movie::{self, KeyChange};
+
+//Runs the interpreter without a window or sound, for scripts and automated checks
+
+//Runs whole frames until the ROM exits or frames have passed.  None runs until the ROM exits.
+//Keys are pressed on the frames inputs says, which must be in frame order.  Returns the number of frames that were run
+pub fn run(
+    chip8: &mut ChipEight,
+    frames: Option<u32>,
+    instructions_per_frame: u32,
+    inputs: &[KeyChange],
+) -> Result<u32, ChipError> {
+    let mut frame = 0;
+    while frames.is_none_or(|frames| frame < frames) {
+        if let Some(keys) = movie::keys_at(inputs, frame) {
+            chip8.key = keys;
+        }
+        for _ in 0..instructions_per_frame {
+            if chip8.emulation_cycle()? == StepOutcome::Exited {
+                return Ok(frame);
+            }
+        }
+        chip8.tick_timers();
+        frame += 1;
+    }
+    Ok(frame)
+}
+
+//Parses scripted input like "60:5 70:- 120:4a 130:-".  Each entry is a frame and the hex keys held from then on.
+//- releases every key.  Frames must be in increasing order
+pub fn parse_key_script(script: &str) -> Result<Vec<KeyChange>, String> {
+    let mut changes: Vec<KeyChange> = Vec::new();
+    for entry in script.split(|c: char| c.is_whitespace() || c == ',') {
+        if entry.is_empty() {
+            continue;
+        }
+        let (frame, held) = match entry.split_once(':') {
+            Some(parts) => parts,
+            None => return Err(format!("'{}' should be frame:keys", entry)),
+        };
+        let frame: u32 = frame
+            .parse()
+            .map_err(|_| format!("'{}' is not a frame number", frame))?;
+        if changes.last().is_some_and(|last| last.frame >= frame) {
+            return Err(format!("frame {} is out of order", frame));
+        }
+
+        let mut keys = [false; 16];
+        if held != "-" {
+            for key in held.chars() {
+                let key = key
+                    .to_digit(16)
+                    .ok_or_else(|| format!("'{}' is not a hex key", key))?;
+                keys[key as usize] = true;
+            }
+        }
+        changes.push(KeyChange { frame, keys });
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_eight::tests::machine;
+
+    fn keys(held: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for &key in held {
+            keys[key] = true;
+        }
+        keys
+    }
+
+    #[test]
+    fn key_scripts_list_the_keys_held_from_each_frame() {
+        let changes = parse_key_script("60:5 70:-,120:4a  130:-").unwrap();
+        let frames: Vec<u32> = changes.iter().map(|change| change.frame).collect();
+        assert_eq!(frames, [60, 70, 120, 130]);
+        assert_eq!(changes[0].keys, keys(&[5]));
+        assert_eq!(changes[1].keys, keys(&[]));
+        assert_eq!(changes[2].keys, keys(&[4, 0xA]));
+        assert_eq!(parse_key_script("").unwrap(), []);
+    }
+
+    #[test]
+    fn bad_key_scripts_are_rejected() {
+        assert_eq!(
+            parse_key_script("60"),
+            Err("'60' should be frame:keys".to_string())
+        );
+        assert_eq!(
+            parse_key_script("x:5"),
+            Err("'x' is not a frame number".to_string())
+        );
+        assert_eq!(
+            parse_key_script("-1:5"),
+            Err("'-1' is not a frame number".to_string())
+        );
+        assert_eq!(
+            parse_key_script("60:5g"),
+            Err("'g' is not a hex key".to_string())
+        );
+        assert_eq!(
+            parse_key_script("60:5 60:-"),
+            Err("frame 60 is out of order".to_string())
+        );
+    }
+
+    #[test]
+    fn runs_stop_after_the_frames_or_when_the_rom_exits() {
+        //Counts frames in V0 until key 1 is held, then exits
+        let program = [0x7001, 0x6101, 0xE19E, 0x1200, 0x00FD];
+        let mut chip8 = machine(&program);
+        assert_eq!(run(&mut chip8, Some(5), 4, &[]).unwrap(), 5);
+        assert_eq!(chip8.v_registers()[0], 5);
+
+        let mut chip8 = machine(&program);
+        let inputs = parse_key_script("2:1").unwrap();
+        assert_eq!(run(&mut chip8, None, 4, &inputs).unwrap(), 2);
+        assert!(chip8.exited());
+    }
+}
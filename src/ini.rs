@@ -0,0 +1,190 @@
+//Minimal INI reader for the config files: [section] headers, name = value entries and ; comments.
+//Sections named "kind" apply to every ROM and sections named "kind <rom>" only to the ROM with that
+//file name or 16 digit hash, so one file can hold defaults and per-ROM overrides
+
+pub struct Entry {
+    pub line: usize, //1 based, for error messages
+    pub name: String,
+    pub value: String,
+}
+
+impl Entry {
+    //The value as a hex keypad key 0-F
+    pub fn keypad_key(&self) -> Result<u8, String> {
+        match u8::from_str_radix(&self.value, 16) {
+            Ok(key) if self.value.len() == 1 => Ok(key),
+            _ => Err(format!(
+                "line {}: '{}' is not a keypad key 0-F",
+                self.line, self.value
+            )),
+        }
+    }
+}
+
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+pub fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        //Split on the last = so "= = 5" can bind the equals key
+        let (name, value) = match line.rsplit_once('=') {
+            Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+            _ => return Err(format!("line {}: expected name = value", line_number)),
+        };
+        let section = sections
+            .last_mut()
+            .ok_or_else(|| format!("line {}: entry before the first [section]", line_number))?;
+        section.entries.push(Entry {
+            line: line_number,
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(sections)
+}
+
+//A ; starts a comment at the start of a line or after whitespace, and is part of the text anywhere else.
+//"; = 5" is the one line starting with ; that isn't a comment, so the semicolon key can be bound
+fn strip_comment(line: &str) -> &str {
+    let start = line.len() - line.trim_start().len();
+    let binds_semicolon = line[start..]
+        .strip_prefix(';')
+        .is_some_and(|rest| rest.trim_start().starts_with('='));
+
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        let starts_comment = previous.is_none_or(char::is_whitespace);
+        if c == ';' && starts_comment && !(binds_semicolon && i == start) {
+            return &line[..i];
+        }
+        previous = Some(c);
+    }
+    line
+}
+
+//The sections of a kind that apply to a ROM.  Sections for every ROM come first so per-ROM ones override them
+pub fn sections_for<'a>(
+    sections: &'a [Section],
+    kind: &str,
+    rom_name: &str,
+    rom_hash: u64,
+) -> Vec<&'a Section> {
+    let hash = format!("{:016x}", rom_hash);
+    let for_rom = |section: &&Section| {
+        let mut words = section.name.splitn(2, char::is_whitespace);
+        if words.next() != Some(kind) {
+            return None;
+        }
+        match words.next().map(str::trim) {
+            None => Some(false),
+            Some(rom) if rom == rom_name || rom.eq_ignore_ascii_case(&hash) => Some(true),
+            Some(_) => None,
+        }
+    };
+
+    let mut applicable: Vec<&Section> = sections
+        .iter()
+        .filter(|section| for_rom(section) == Some(false))
+        .collect();
+    applicable.extend(
+        sections
+            .iter()
+            .filter(|section| for_rom(section) == Some(true)),
+    );
+    applicable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(section: &Section) -> Vec<(&str, &str)> {
+        section
+            .entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn sections_hold_their_entries() {
+        let sections = parse("[keys]\nQ = 4\n  W=5  \n\n[ controller ]\na = 1\n").unwrap();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].name, "keys");
+        assert_eq!(entries(&sections[0]), [("Q", "4"), ("W", "5")]);
+        assert_eq!(sections[1].name, "controller");
+        assert_eq!(sections[1].entries[0].line, 6);
+    }
+
+    #[test]
+    fn comments_start_a_line_or_follow_whitespace() {
+        let text = "; layout for pong\n[keys] ; defaults\nQ = 4 ; jump\n  ; W = 5\n; = 6\nscancode:;x = 7;\n";
+        let sections = parse(text).unwrap();
+        assert_eq!(sections[0].name, "keys");
+        assert_eq!(
+            entries(&sections[0]),
+            [("Q", "4"), (";", "6"), ("scancode:;x", "7;")]
+        );
+    }
+
+    #[test]
+    fn equals_can_be_bound() {
+        let sections = parse("[keys]\n= = 5\n").unwrap();
+        assert_eq!(entries(&sections[0]), [("=", "5")]);
+    }
+
+    #[test]
+    fn malformed_lines_report_their_line() {
+        assert_eq!(
+            parse("[keys]\nQ\n").err(),
+            Some("line 2: expected name = value".to_string())
+        );
+        assert_eq!(
+            parse("; comment\nQ = 4\n").err(),
+            Some("line 2: entry before the first [section]".to_string())
+        );
+        assert_eq!(
+            parse("[keys]\nQ = G\n").unwrap()[0].entries[0].keypad_key(),
+            Err("line 2: 'G' is not a keypad key 0-F".to_string())
+        );
+    }
+
+    #[test]
+    fn rom_sections_follow_the_general_ones() {
+        let sections = parse(
+            "[keys pong.ch8]\n[keys]\n[keys 00000000000000ab]\n[keys tetris.ch8]\n[controller]\n",
+        )
+        .unwrap();
+        let names = |rom_name, rom_hash| -> Vec<String> {
+            sections_for(&sections, "keys", rom_name, rom_hash)
+                .iter()
+                .map(|section| section.name.clone())
+                .collect()
+        };
+        assert_eq!(
+            names("pong.ch8", 0xAB),
+            ["keys", "keys pong.ch8", "keys 00000000000000ab"]
+        );
+        assert_eq!(names("brix.ch8", 0), ["keys"]);
+    }
+}
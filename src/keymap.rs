@@ -0,0 +1,156 @@
+use crate::ini;
+
+use std::collections::HashMap;
+
+//Which keyboard keys press which hex keypad keys.  Keys are named the way SDL names them ("Q", "Left",
+//"Keypad 8", "Space") so this doesn't depend on SDL.  Keycode names follow the keyboard layout, so Q is
+//wherever the layout puts Q.  Names prefixed with "scancode:" are physical positions instead, so
+//"scancode:Q" is the key left of W on any layout.
+//
+//Config file:
+//  [keys]            ; replaces the default layout
+//  X = 0
+//  scancode:1 = 1
+//  [keys pong.ch8]   ; adds to or overrides [keys] for one ROM, by file name or hash
+//  Up = 1
+//  Down = 4
+
+//The keypad is laid out on the left of a QWERTY keyboard:
+//  1 2 3 C        1 2 3 4
+//  4 5 6 D   ->   Q W E R
+//  7 8 9 E        A S D F
+//  A 0 B F        Z X C V
+const DEFAULT_LAYOUT: [(&str, u8); 16] = [
+    ("1", 0x1),
+    ("2", 0x2),
+    ("3", 0x3),
+    ("4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+];
+
+const SCANCODE_PREFIX: &str = "scancode:";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    keycodes: HashMap<String, u8>, //lower case key name -> keypad key
+    scancodes: HashMap<String, u8>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap::empty();
+        for &(name, key) in DEFAULT_LAYOUT.iter() {
+            keymap.keycodes.insert(name.to_ascii_lowercase(), key);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    fn empty() -> Self {
+        Keymap {
+            keycodes: HashMap::new(),
+            scancodes: HashMap::new(),
+        }
+    }
+
+    //Builds the keymap for one ROM from the parsed config file
+    pub fn from_config(
+        sections: &[ini::Section],
+        rom_name: &str,
+        rom_hash: u64,
+    ) -> Result<Self, String> {
+        let sections = ini::sections_for(sections, "keys", rom_name, rom_hash);
+
+        let mut keymap = if sections.iter().any(|section| section.name == "keys") {
+            Keymap::empty()
+        } else {
+            Keymap::default()
+        };
+        for entry in sections.iter().flat_map(|section| section.entries.iter()) {
+            let key = entry.keypad_key()?;
+            let name = entry.name.to_ascii_lowercase();
+            match name.strip_prefix(SCANCODE_PREFIX) {
+                Some(scancode) => keymap.scancodes.insert(scancode.trim().to_string(), key),
+                None => keymap.keycodes.insert(name, key),
+            };
+        }
+        Ok(keymap)
+    }
+
+    pub fn keycode(&self, name: &str) -> Option<u8> {
+        self.keycodes.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    pub fn scancode(&self, name: &str) -> Option<u8> {
+        self.scancodes.get(&name.to_ascii_lowercase()).copied()
+    }
+
+    //Names for the frontend to check against the keys it knows
+    pub fn keycode_names(&self) -> impl Iterator<Item = &str> {
+        self.keycodes.keys().map(String::as_str)
+    }
+
+    pub fn scancode_names(&self) -> impl Iterator<Item = &str> {
+        self.scancodes.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_config(config: &str, rom_name: &str, rom_hash: u64) -> Result<Keymap, String> {
+        Keymap::from_config(&ini::parse(config).unwrap(), rom_name, rom_hash)
+    }
+
+    #[test]
+    fn default_is_qwerty() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.keycode("X"), Some(0x0));
+        assert_eq!(keymap.keycode("4"), Some(0xC));
+        assert_eq!(keymap.keycode("v"), Some(0xF));
+        assert_eq!(keymap.keycode("Space"), None);
+    }
+
+    #[test]
+    fn keys_section_replaces_the_default() {
+        let config = "[keys]\n; Dvorak\nQ = 0\nscancode:Keypad 8 = 2\n";
+        let keymap = from_config(config, "pong.ch8", 0).unwrap();
+        assert_eq!(keymap.keycode("q"), Some(0x0));
+        assert_eq!(keymap.keycode("X"), None);
+        assert_eq!(keymap.scancode("Keypad 8"), Some(0x2));
+    }
+
+    #[test]
+    fn rom_sections_override_for_that_rom_only() {
+        let config = "[keys pong.ch8]\nUp = 1\nQ = 2\n[keys 00000000000000ab]\nDown = 4\n";
+        let pong = from_config(config, "pong.ch8", 1).unwrap();
+        assert_eq!(pong.keycode("Up"), Some(0x1));
+        assert_eq!(pong.keycode("Q"), Some(0x2));
+        assert_eq!(pong.keycode("W"), Some(0x5));
+        assert_eq!(pong.keycode("Down"), None);
+
+        let other = from_config(config, "other.ch8", 0xAB).unwrap();
+        assert_eq!(other.keycode("Up"), None);
+        assert_eq!(other.keycode("Down"), Some(0x4));
+    }
+
+    #[test]
+    fn bad_entries_report_their_line() {
+        let error = from_config("[keys]\nQ = 10\n", "", 0).unwrap_err();
+        assert!(error.starts_with("line 2:"));
+        assert!(ini::parse("Q = 1\n").is_err());
+    }
+}
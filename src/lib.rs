@@ -0,0 +1,21 @@
+//Core Chip8 interpreter.  Has no dependency on SDL so it can be built and tested headless.
+//The SDL frontend lives in the binary behind the "sdl" feature.
+pub mod assembler;
+pub mod audio;
+pub mod chip_eight;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod gamepad;
+pub mod headless;
+pub mod ini;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod phosphor;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod save_state;
+pub mod screenshot;
+pub mod storage;
@@ -1,63 +1,248 @@
-mod chip_eight;
+mod cli;
+#[cfg(feature = "sdl")]
+mod frontend;
+#[cfg(feature = "sdl")]
+mod sdl_audio;
+#[cfg(feature = "sdl")]
+mod sdl_gamepad;
+#[cfg(feature = "sdl")]
 mod user_interface;
-use chip_eight::*;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+
+use chip_eight_emulator::assembler;
+use chip_eight_emulator::chip_eight::ChipEight;
+use chip_eight_emulator::disassembler::{self, Syntax};
+use chip_eight_emulator::error::ChipError;
+use chip_eight_emulator::headless;
+use chip_eight_emulator::movie::Movie;
+use chip_eight_emulator::screenshot::{self, ImageFormat};
+use cli::{Command, Options};
 use std::env;
-use std::time::Duration;
-use user_interface::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+usage:
+  chip_eight_emulator [options] <rom>        run a ROM
+  chip_eight_emulator disasm <rom> [--octo]  print a disassembly of a ROM
+  chip_eight_emulator asm <source> <rom>     assemble classic mnemonics into a ROM
 
-const DISPLAY_WIDTH: usize = 64;
-const DISPLAY_HEIGHT: usize = 32;
-const DISPLAY_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+options:
+  --scale <n>          window pixels per Chip8 pixel (default 10)
+  --ipf <n>            instructions run per 60 Hz frame (default 10)
+  --cpu-hz <n>         instructions run per second, as an alternative to --ipf.  Rounded to
+                       a whole number of instructions per frame
+  --quirks <profile>   default, vip, chip48 or schip
+  --palette <colours>  mono, amber, green or lcd, or hex colours for off and on pixels, then
+                       optionally XO-CHIP plane 2 and both planes: 000000,FFFFFF[,AAAAAA,555555]
+  --mute               start with sound off
+  --waveform <shape>   beep waveform: square (default), sine, triangle or sawtooth
+  --frequency <hz>     beep pitch (default 440)
+  --volume <level>     beep volume from 0 to 1 (default 0.25)
+  --phosphor <decay>   fade pixels out over several frames to hide flicker.  0.5 keeps
+                       half their brightness each frame
+  --rewind <seconds>   how far back Backspace can rewind (default 30).  0 turns rewind off
+  --keymap <file>      keyboard and controller mapping config (default keymap.ini in the data directory)
+  --headless           run without a window or sound.  Needs --frames or --play
+  --frames <n>         stop after n frames
+  --seed <n>           seed for the CXNN random numbers
+  --record <file>      record the keypad into a movie file
+  --play <file>        replay a movie recorded with --record
+  --keys <script>      headless input: frame:keys entries such as \"60:5 70:- 90:4a\"
+  --dump <file>        after a headless run write the display as .png, .pbm or
+                       ASCII art for any other name.  - prints the ASCII art
+  -h, --help           show this message";
 
 fn main() {
-    let mut my_chip8: ChipEight;
-    my_chip8 = ChipEight::new();
     let args: Vec<String> = env::args().collect();
-    let scale: usize = 10;
-
-    //println!("Path =  {}", args[1]);
-
-    my_chip8.load_rom(&args[1]);  //Element 0 is the path to the .exe.  Element 1 is the path given when the program starts
-    //my_chip8.load_rom("C:\\Repos\\SpaceInvaders[DavidWinter].ch8"); //This line is just to use for debug.  Not sure how to start the debugger with cmd arguments
-    //my_chip8.load_rom("C:\\Repos\\Pong[PaulVervalin].ch8");
-    //my_chip8.load_rom("C:\\Repos\\AstroDodge[RevivalStudios].ch8");
-
-    let sdl_context = sdl2::init().unwrap();
-    let mut my_user_interface = UserInterface::new(&sdl_context, scale);
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut quit = false;
-
-    while quit == false {
-        //Emulation Cycle
-        my_chip8.emulation_cycle();
-
-        //render graphics
-        my_user_interface.render(&my_chip8);
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => {
-                    quit = true;
-                }
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => my_user_interface.key_press(&mut my_chip8, keycode),
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => my_user_interface.key_release(&mut my_chip8, keycode),
-
-                _ => {}
-            }
-        }
-
-        ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 600));
+
+    //Element 0 is the path to the .exe.  Element 1 is the path given when the program starts
+    match args.get(1).map(String::as_str) {
+        Some("disasm") => disasm(&args[2..]),
+        Some("asm") => asm(&args[2..]),
+        Some(_) => run(&args[1..]),
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    }
+}
+
+fn run(args: &[String]) {
+    let mut options = match cli::parse(args) {
+        Ok(Command::Run(options)) => options,
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}.  Run with --help for usage", e);
+            process::exit(1);
+        }
+    };
+
+    let mut chip8 = load_rom(&options);
+    let movie = options.play.as_ref().map(|path| load_movie(path));
+    if let Some(movie) = &movie {
+        if let Err(e) = movie.start_playback(&mut chip8) {
+            eprintln!("Could not play {}: {}", options.play.as_ref().unwrap(), e);
+            process::exit(1);
+        }
+        //The replay only matches if it runs at the speed it was recorded at
+        options.instructions_per_frame = movie.instructions_per_frame;
+    }
+
+    if options.headless {
+        run_headless(chip8, &options, movie);
+    } else {
+        run_window(chip8, &options, movie);
+    }
+}
+
+fn load_movie(path: &str) -> Movie {
+    let result = fs::read(path)
+        .map_err(ChipError::from)
+        .and_then(|data| Movie::from_bytes(&data));
+    match result {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("Could not load {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+//Builds the machine the options describe with the ROM loaded, or exits with an explanation
+fn load_rom(options: &Options) -> ChipEight {
+    let rom = match fs::read(&options.rom_path) {
+        Ok(rom) => rom,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!("ROM not found: {}", options.rom_path);
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Could not read {}: {}", options.rom_path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut chip8 = ChipEight::new(options.quirks);
+    if let Some(seed) = options.seed {
+        chip8.set_seed(seed);
+    }
+    if let Err(e) = chip8.load_rom_data(&rom) {
+        eprintln!("Could not load {}: {}", options.rom_path, e);
+        process::exit(1);
+    }
+    chip8
+}
+
+fn run_headless(mut chip8: ChipEight, options: &Options, movie: Option<Movie>) {
+    //Replays stop where the recording did unless told otherwise
+    let frames = options
+        .frames
+        .or_else(|| movie.as_ref().map(|movie| movie.length));
+    let inputs = match (&movie, &options.keys) {
+        (Some(movie), _) => &movie.changes[..],
+        (None, Some(keys)) => &keys[..],
+        (None, None) => &[],
+    };
+
+    let result = headless::run(&mut chip8, frames, options.instructions_per_frame, inputs);
+    //The screen is written even after a crash since it often shows what went wrong
+    if let Some(path) = &options.dump {
+        dump_display(&chip8, path, options);
+    }
+    //Status goes to stderr so the ASCII art is alone on stdout and can be compared against a file
+    match result {
+        Ok(frames) => eprintln!("Ran {} frames with seed {}", frames, chip8.seed()),
+        Err(e) => {
+            eprintln!("ROM crashed: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn dump_display(chip8: &ChipEight, path: &str, options: &Options) {
+    let result = if path == "-" {
+        let image = screenshot::encode(chip8, ImageFormat::Ascii, &options.palette);
+        io::stdout().write_all(&image)
+    } else {
+        let format = ImageFormat::from_path(Path::new(path));
+        fs::write(path, screenshot::encode(chip8, format, &options.palette))
+    };
+    if let Err(e) = result {
+        eprintln!("Could not write {}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_window(chip8: ChipEight, options: &Options, movie: Option<Movie>) {
+    if let Err(e) = frontend::run(chip8, options, movie) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_window(_chip8: ChipEight, _options: &Options, _movie: Option<Movie>) {
+    eprintln!("This build has no display.  Rebuild with --features sdl or pass --headless");
+    process::exit(1);
+}
+
+fn disasm(args: &[String]) {
+    let syntax = if args.iter().any(|arg| arg == "--octo") {
+        Syntax::Octo
+    } else {
+        Syntax::Classic
+    };
+    let rom_path = match args.iter().find(|arg| !arg.starts_with("--")) {
+        Some(rom_path) => rom_path,
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let rom = match fs::read(rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", rom_path, e);
+            process::exit(1);
+        }
+    };
+    for line in disassembler::disassemble(&rom, syntax) {
+        println!("{}", line);
+    }
+}
+
+fn asm(args: &[String]) {
+    let (source_path, rom_path) = match args {
+        [source_path, rom_path] => (source_path, rom_path),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    let source = match fs::read_to_string(source_path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Could not read {}: {}", source_path, e);
+            process::exit(1);
+        }
+    };
+    let rom = match assembler::assemble(&source) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: {}", source_path, e);
+            process::exit(1);
+        }
+    };
+    if let Err(e) = fs::write(rom_path, &rom) {
+        eprintln!("Could not write {}: {}", rom_path, e);
+        process::exit(1);
     }
 }
@@ -0,0 +1,286 @@
+use crate::chip_eight::{ChipEight, RPL_FLAG_COUNT};
+use crate::error::ChipError;
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::save_state::{StateReader, StateWriter};
+
+//A recording of the keypad that replays a session exactly.  Everything else that can change what a ROM does
+//(the ROM, CXNN seed, quirks, speed and RPL flags) is stored in the header so playback starts from the same machine.
+//
+//Movie file layout.  All values are little endian.
+//  magic                   4 bytes   "C8MV"
+//  version                 u16       MOVIE_VERSION
+//  rom hash                u64       FNV-1a hash of the ROM the movie was recorded with
+//  seed                    u64       CXNN seed
+//  quirks                  4 bytes   shift_uses_vy, load_store_increment, jump_uses_vx, wrap_sprites
+//  instructions per frame  u32
+//  rpl flags               16 bytes  flags the ROM started with
+//  length                  u32       frames recorded
+//  change count            u32
+//  changes                 ...       frame u32 then keys u16 with bit n set while key n is held
+
+pub const MOVIE_MAGIC: &[u8; 4] = b"C8MV";
+pub const MOVIE_VERSION: u16 = 1;
+
+//The whole keypad from frame onwards
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    pub frame: u32,
+    pub keys: [bool; 16],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub rpl_flags: [u8; RPL_FLAG_COUNT],
+    pub length: u32,
+    pub changes: Vec<KeyChange>, //In frame order, at most one per frame
+}
+
+impl Movie {
+    //Starts a recording of chip8 as it is now.  Call before the first frame runs
+    pub fn new(chip8: &ChipEight, instructions_per_frame: u32) -> Self {
+        Movie {
+            rom_hash: chip8.rom_hash(),
+            seed: chip8.seed(),
+            quirks: chip8.quirks(),
+            instructions_per_frame,
+            rpl_flags: *chip8.rpl_flags(),
+            length: 0,
+            changes: Vec::new(),
+        }
+    }
+
+    //Call at the start of every frame with the keys the frame will run with.  Only changes are stored
+    pub fn record(&mut self, frame: u32, keys: &[bool; 16]) {
+        let held = self
+            .changes
+            .last()
+            .map_or([false; 16], |change| change.keys);
+        if *keys == held {
+            return;
+        }
+        match self.changes.last_mut() {
+            //The debugger can hold a frame while keys keep changing.  Only the last change counts
+            Some(change) if change.frame == frame => change.keys = *keys,
+            _ => self.changes.push(KeyChange { frame, keys: *keys }),
+        }
+    }
+
+    //Puts chip8 back the way it was when the recording started.  The ROM must already be loaded
+    pub fn start_playback(&self, chip8: &mut ChipEight) -> Result<(), ChipError> {
+        if chip8.rom_hash() != self.rom_hash {
+            return Err(ChipError::MovieRomMismatch);
+        }
+        chip8.set_seed(self.seed);
+        chip8.set_quirks(self.quirks);
+        chip8.set_rpl_flags(self.rpl_flags);
+        chip8.key = [false; 16];
+        Ok(())
+    }
+
+    //The keys to hold from frame onwards, if they changed on that frame
+    pub fn keys_at(&self, frame: u32) -> Option<[bool; 16]> {
+        keys_at(&self.changes, frame)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut movie = StateWriter::new();
+        movie.bytes(MOVIE_MAGIC);
+        movie.u16(MOVIE_VERSION);
+        movie.u64(self.rom_hash);
+        movie.u64(self.seed);
+        movie.bool(self.quirks.shift_uses_vy);
+        movie.u8(self.quirks.load_store_increment.to_byte());
+        movie.bool(self.quirks.jump_uses_vx);
+        movie.bool(self.quirks.wrap_sprites);
+        movie.u32(self.instructions_per_frame);
+        movie.bytes(&self.rpl_flags);
+        movie.u32(self.length);
+        movie.u32(self.changes.len() as u32);
+        for change in self.changes.iter() {
+            movie.u32(change.frame);
+            movie.u16(keys_to_bits(&change.keys));
+        }
+        movie.into_bytes()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ChipError> {
+        //The reader is shared with save states.  Report its errors as movie errors
+        Self::read(&mut StateReader::new(data)).map_err(|e| match e {
+            ChipError::InvalidSaveState(reason) => ChipError::InvalidMovie(reason),
+            e => e,
+        })
+    }
+
+    fn read(movie: &mut StateReader) -> Result<Self, ChipError> {
+        if movie.bytes(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return Err(ChipError::InvalidMovie("not a movie"));
+        }
+        if movie.u16()? != MOVIE_VERSION {
+            return Err(ChipError::InvalidMovie("unsupported version"));
+        }
+
+        let rom_hash = movie.u64()?;
+        let seed = movie.u64()?;
+        let quirks = Quirks {
+            shift_uses_vy: movie.bool()?,
+            load_store_increment: LoadStoreIncrement::from_byte(movie.u8()?)
+                .ok_or(ChipError::InvalidMovie("unknown FX55/FX65 quirk"))?,
+            jump_uses_vx: movie.bool()?,
+            wrap_sprites: movie.bool()?,
+        };
+        let instructions_per_frame = movie.u32()?;
+        let mut rpl_flags = [0; RPL_FLAG_COUNT];
+        rpl_flags.copy_from_slice(movie.bytes(RPL_FLAG_COUNT)?);
+        let length = movie.u32()?;
+
+        let count = movie.u32()?;
+        let mut changes = Vec::new();
+        for _ in 0..count {
+            let frame = movie.u32()?;
+            if changes
+                .last()
+                .is_some_and(|previous: &KeyChange| previous.frame >= frame)
+            {
+                return Err(ChipError::InvalidMovie("key changes are out of order"));
+            }
+            changes.push(KeyChange {
+                frame,
+                keys: bits_to_keys(movie.u16()?),
+            });
+        }
+
+        Ok(Movie {
+            rom_hash,
+            seed,
+            quirks,
+            instructions_per_frame,
+            rpl_flags,
+            length,
+            changes,
+        })
+    }
+}
+
+//changes must be in frame order
+pub fn keys_at(changes: &[KeyChange], frame: u32) -> Option<[bool; 16]> {
+    changes
+        .binary_search_by_key(&frame, |change| change.frame)
+        .ok()
+        .map(|i| changes[i].keys)
+}
+
+fn keys_to_bits(keys: &[bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .fold(0, |bits, (key, &held)| bits | (held as u16) << key)
+}
+
+fn bits_to_keys(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, held) in keys.iter_mut().enumerate() {
+        *held = bits & (1 << key) != 0;
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip_eight::tests::{machine, machine_with};
+
+    fn keys(held: &[usize]) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for &key in held {
+            keys[key] = true;
+        }
+        keys
+    }
+
+    fn recording() -> Movie {
+        let mut chip8 = machine_with(Quirks::cosmac_vip(), &[0x1200]);
+        chip8.set_seed(1234);
+        chip8.set_rpl_flags([7; RPL_FLAG_COUNT]);
+        let mut movie = Movie::new(&chip8, 15);
+        movie.record(0, &keys(&[]));
+        movie.record(3, &keys(&[5]));
+        movie.record(3, &keys(&[5, 0xA]));
+        movie.record(4, &keys(&[5, 0xA]));
+        movie.record(9, &keys(&[]));
+        movie.length = 12;
+        movie
+    }
+
+    #[test]
+    fn only_key_changes_are_recorded() {
+        let movie = recording();
+        assert_eq!(
+            movie.changes,
+            vec![
+                KeyChange {
+                    frame: 3,
+                    keys: keys(&[5, 0xA])
+                },
+                KeyChange {
+                    frame: 9,
+                    keys: keys(&[])
+                },
+            ]
+        );
+        assert_eq!(movie.keys_at(3), Some(keys(&[5, 0xA])));
+        assert_eq!(movie.keys_at(4), None);
+    }
+
+    #[test]
+    fn movies_round_trip_through_bytes() {
+        let movie = recording();
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        let mut bytes = recording().to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(ChipError::InvalidMovie("not a movie"))
+        ));
+
+        let mut bytes = recording().to_bytes();
+        bytes[4..6].copy_from_slice(&(MOVIE_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Movie::from_bytes(&bytes),
+            Err(ChipError::InvalidMovie("unsupported version"))
+        ));
+
+        let bytes = recording().to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ChipError::InvalidMovie(_))
+        ));
+    }
+
+    #[test]
+    fn playback_restores_the_recorded_machine() {
+        let movie = recording();
+        let mut chip8 = machine(&[0x1200]);
+        chip8.key[3] = true;
+        movie.start_playback(&mut chip8).unwrap();
+        assert_eq!(chip8.seed(), 1234);
+        assert_eq!(chip8.quirks(), Quirks::cosmac_vip());
+        assert_eq!(chip8.rpl_flags(), &[7; RPL_FLAG_COUNT]);
+        assert_eq!(chip8.key, [false; 16]);
+    }
+
+    #[test]
+    fn playback_needs_the_same_rom() {
+        let mut chip8 = machine(&[0x1202]);
+        assert!(matches!(
+            recording().start_playback(&mut chip8),
+            Err(ChipError::MovieRomMismatch)
+        ));
+    }
+}
@@ -0,0 +1,123 @@
+//Colours the frontend draws each display value with.  Display bytes hold one bit per XO-CHIP plane,
+//so index 0 is an unlit pixel, 1 is plane 1, 2 is plane 2 and 3 is both
+
+pub type Rgb = (u8, u8, u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+//Built in palettes that --palette takes by name and the P hotkey cycles through.  The first is the default
+pub const PRESETS: [(&str, Palette); 4] = [
+    (
+        "mono",
+        Palette {
+            colors: [(0, 0, 0), (255, 255, 255), (170, 170, 170), (85, 85, 85)],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            colors: [(24, 12, 0), (255, 176, 0), (170, 100, 0), (255, 224, 140)],
+        },
+    ),
+    //Green phosphor monitor
+    (
+        "green",
+        Palette {
+            colors: [(4, 20, 8), (51, 255, 102), (20, 140, 60), (190, 255, 200)],
+        },
+    ),
+    //Original Game Boy screen, dark pixels on a pale green background
+    (
+        "lcd",
+        Palette {
+            colors: [(155, 188, 15), (15, 56, 15), (139, 172, 15), (48, 98, 48)],
+        },
+    ),
+];
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+impl Palette {
+    //A preset name, or comma separated hex colours in display value order starting with the background:
+    //"000000,FFFFFF".  Plane 2 and both-plane colours are optional and keep their defaults when left out
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if let Some(palette) = Palette::preset(text) {
+            return Ok(palette);
+        }
+        let colors: Vec<&str> = text.split(',').map(str::trim).collect();
+        if colors.len() < 2 || colors.len() > 4 {
+            return Err(format!(
+                "expected a preset ({}) or 2 to 4 comma separated colours, found '{}'",
+                preset_names().join(", "),
+                text
+            ));
+        }
+
+        let mut palette = Palette::default();
+        for (slot, color) in palette.colors.iter_mut().zip(colors.iter()) {
+            *slot = parse_color(color)?;
+        }
+        Ok(palette)
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name.trim()))
+            .map(|&(_, palette)| palette)
+    }
+
+    //The preset this palette matches, if any
+    pub fn name(&self) -> Option<&'static str> {
+        PRESETS
+            .iter()
+            .find(|(_, palette)| palette == self)
+            .map(|&(name, _)| name)
+    }
+}
+
+fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|&(name, _)| name).collect()
+}
+
+//RRGGBB with an optional leading #
+fn parse_color(text: &str) -> Result<Rgb, String> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    let value = match u32::from_str_radix(hex, 16) {
+        Ok(value) if hex.len() == 6 => value,
+        _ => return Err(format!("'{}' is not a RRGGBB hex colour", text)),
+    };
+    Ok(((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_takes_presets_and_colours() {
+        assert_eq!(Palette::parse("Amber").unwrap(), PRESETS[1].1);
+        assert_eq!(Palette::parse("amber").unwrap().name(), Some("amber"));
+
+        let palette = Palette::parse("#102030, ffffff").unwrap();
+        assert_eq!(palette.colors[0], (0x10, 0x20, 0x30));
+        assert_eq!(palette.colors[1], (255, 255, 255));
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+        assert_eq!(palette.name(), None);
+    }
+
+    #[test]
+    fn parse_rejects_bad_colours() {
+        assert!(Palette::parse("000000").is_err());
+        assert!(Palette::parse("000000,FFFFFF,000000,FFFFFF,000000").is_err());
+        assert!(Palette::parse("000000,FFFFFG").is_err());
+        assert!(Palette::parse("sepia").is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::palette::{Palette, Rgb};
+
+//Afterglow filter for the display.  CHIP-8 games move sprites by XOR erasing and redrawing them, so a
+//sprite that happens to be erased when a frame ends vanishes for that frame and strobes.  Like a CRT
+//phosphor, pixels here light up at once but fade back to the background over the next few frames
+
+pub struct Phosphor {
+    decay: f32, //Fraction of its brightness a pixel keeps each frame after going dark
+    //Display value each pixel last showed and how brightly it still glows
+    glow: Vec<(u8, f32)>,
+}
+
+impl Phosphor {
+    pub fn new(decay: f32) -> Self {
+        Phosphor {
+            decay,
+            glow: Vec::new(),
+        }
+    }
+
+    //Call once per 60 Hz frame with the display as the frame ended
+    pub fn end_frame(&mut self, display: &[u8]) {
+        //A resolution change redraws the whole screen, so old glow has nothing to line up with
+        if self.glow.len() != display.len() {
+            self.glow = vec![(0, 0.0); display.len()];
+        }
+        for (glow, &pixel) in self.glow.iter_mut().zip(display.iter()) {
+            if pixel != 0 {
+                *glow = (pixel, 1.0);
+            } else {
+                glow.1 *= self.decay;
+            }
+        }
+    }
+
+    //The colour to draw a pixel that currently holds pixel
+    pub fn color(&self, index: usize, pixel: u8, palette: &Palette) -> Rgb {
+        match self.glow.get(index) {
+            Some(&(glow, brightness)) if pixel == 0 => blend(
+                palette.colors[0],
+                palette.colors[glow as usize & 0b11],
+                brightness,
+            ),
+            _ => palette.colors[pixel as usize & 0b11],
+        }
+    }
+}
+
+fn blend(background: Rgb, color: Rgb, amount: f32) -> Rgb {
+    let channel =
+        |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+    (
+        channel(background.0, color.0),
+        channel(background.1, color.1),
+        channel(background.2, color.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dark_pixels_fade_to_the_background() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(0.5);
+        phosphor.end_frame(&[1, 0]);
+        assert_eq!(phosphor.color(0, 1, &palette), (255, 255, 255));
+        assert_eq!(phosphor.color(1, 0, &palette), (0, 0, 0));
+
+        //Erased at the end of the next frame, so it glows at half brightness and then a quarter
+        phosphor.end_frame(&[0, 0]);
+        assert_eq!(phosphor.color(0, 0, &palette), (128, 128, 128));
+        phosphor.end_frame(&[0, 0]);
+        assert_eq!(phosphor.color(0, 0, &palette), (64, 64, 64));
+
+        //Relighting is immediate
+        assert_eq!(phosphor.color(0, 1, &palette), (255, 255, 255));
+    }
+
+    #[test]
+    fn resolution_changes_clear_the_glow() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(0.9);
+        phosphor.end_frame(&[1; 4]);
+        phosphor.end_frame(&[0; 8]);
+        assert_eq!(phosphor.color(0, 0, &palette), (0, 0, 0));
+    }
+}
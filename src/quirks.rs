@@ -0,0 +1,96 @@
+//Different Chip8 interpreters disagree on how a handful of instructions behave.
+//Quirks selects which interpretation ChipEight uses so ROMs written for any of them run unmodified.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Quirks {
+    //8XY6/8XYE: true shifts Vy and stores the result in Vx (COSMAC VIP).  false shifts Vx in place and ignores Vy
+    pub shift_uses_vy: bool,
+    //FX55/FX65: how far I moves after storing/loading V0 to VX
+    pub load_store_increment: LoadStoreIncrement,
+    //BNNN: true treats the opcode as BXNN and jumps to XNN + Vx (CHIP-48/SUPER-CHIP).  false always adds V0
+    pub jump_uses_vx: bool,
+    //DXYN: true wraps pixels that fall off an edge around to the other side.  false clips them
+    pub wrap_sprites: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoadStoreIncrement {
+    #[default]
+    None, //I is left unchanged (SUPER-CHIP 1.1)
+    X,        //I is increased by X, stopping on the last register (CHIP-48)
+    XPlusOne, //I is left one past the last register (COSMAC VIP)
+}
+
+impl LoadStoreIncrement {
+    //Amount I moves after FX55/FX65 with register X
+    pub fn amount(self, x: usize) -> usize {
+        match self {
+            LoadStoreIncrement::None => 0,
+            LoadStoreIncrement::X => x,
+            LoadStoreIncrement::XPlusOne => x + 1,
+        }
+    }
+
+    //Byte stored in movie files
+    pub fn to_byte(self) -> u8 {
+        match self {
+            LoadStoreIncrement::None => 0,
+            LoadStoreIncrement::X => 1,
+            LoadStoreIncrement::XPlusOne => 2,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(LoadStoreIncrement::None),
+            1 => Some(LoadStoreIncrement::X),
+            2 => Some(LoadStoreIncrement::XPlusOne),
+            _ => None,
+        }
+    }
+}
+
+//Names accepted by from_profile
+pub const PROFILES: [&str; 4] = ["default", "vip", "chip48", "schip"];
+
+impl Quirks {
+    pub fn from_profile(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "default" => Some(Quirks::default()),
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::super_chip()),
+            _ => None,
+        }
+    }
+
+    //Original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            wrap_sprites: false,
+        }
+    }
+
+    //CHIP-48 on the HP-48
+    pub fn chip48() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::X,
+            jump_uses_vx: true,
+            wrap_sprites: false,
+        }
+    }
+
+    //SUPER-CHIP 1.1
+    pub fn super_chip() -> Self {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::None,
+            jump_uses_vx: true,
+            wrap_sprites: false,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+//Keeps a history of recent save states so gameplay can be stepped backwards one frame at a time.
+//Only the newest snapshot is stored whole.  Each older frame is a delta that turns the snapshot after it back into it.
+
+enum Delta {
+    Full(Vec<u8>), //Snapshot size changed so the whole previous snapshot is kept
+    Changes(Vec<(usize, Vec<u8>)>), //Runs of bytes (offset, previous bytes) that differ
+}
+
+impl Delta {
+    //Builds the delta that turns current back into previous
+    fn between(previous: &[u8], current: &[u8]) -> Self {
+        if previous.len() != current.len() {
+            return Delta::Full(previous.to_vec());
+        }
+
+        let mut changes = Vec::new();
+        let mut i = 0;
+        while i < previous.len() {
+            if previous[i] == current[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < previous.len() && previous[i] != current[i] {
+                i += 1;
+            }
+            changes.push((start, previous[start..i].to_vec()));
+        }
+        Delta::Changes(changes)
+    }
+
+    fn apply(self, snapshot: &mut Vec<u8>) {
+        match self {
+            Delta::Full(previous) => *snapshot = previous,
+            Delta::Changes(changes) => {
+                for (offset, bytes) in changes {
+                    snapshot[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                }
+            }
+        }
+    }
+}
+
+pub struct RewindBuffer {
+    capacity: usize, //Number of frames that can be rewound
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Delta>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer {
+            capacity,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    //Records the snapshot for the frame that just finished.  The oldest frame is dropped once the buffer is full
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(Delta::between(&previous, &snapshot));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    //Steps back one frame and returns that frame's snapshot.  Returns None when there is nothing older to go back to
+    pub fn rewind(&mut self) -> Option<&[u8]> {
+        let delta = self.deltas.pop_back()?;
+        let snapshot = self.latest.as_mut()?;
+        delta.apply(snapshot);
+        Some(snapshot)
+    }
+
+    //Number of frames currently available to rewind
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewind_steps_back_through_changed_bytes() {
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(vec![1, 2, 3, 4]);
+        buffer.push(vec![1, 5, 6, 4]);
+        buffer.push(vec![9, 5, 6, 7]);
+        assert_eq!(buffer.len(), 2);
+
+        assert_eq!(buffer.rewind(), Some(&[1, 5, 6, 4][..]));
+        assert_eq!(buffer.rewind(), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(buffer.rewind(), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn snapshots_that_change_size_are_kept_whole() {
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(vec![1, 2]);
+        buffer.push(vec![3, 4, 5, 6]);
+        buffer.push(vec![3, 4, 5, 7]);
+
+        assert_eq!(buffer.rewind(), Some(&[3, 4, 5, 6][..]));
+        assert_eq!(buffer.rewind(), Some(&[1, 2][..]));
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped_at_capacity() {
+        let mut buffer = RewindBuffer::new(2);
+        for frame in 0..5 {
+            buffer.push(vec![frame]);
+        }
+        assert_eq!(buffer.len(), 2);
+
+        assert_eq!(buffer.rewind(), Some(&[3][..]));
+        assert_eq!(buffer.rewind(), Some(&[2][..]));
+        assert_eq!(buffer.rewind(), None);
+    }
+
+    #[test]
+    fn clear_forgets_the_history() {
+        let mut buffer = RewindBuffer::new(10);
+        buffer.push(vec![1]);
+        buffer.push(vec![2]);
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.rewind(), None);
+
+        //Frames from before the clear can't be reached again
+        buffer.push(vec![3]);
+        buffer.push(vec![4]);
+        assert_eq!(buffer.rewind(), Some(&[3][..]));
+        assert_eq!(buffer.rewind(), None);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::chip_eight::ChipEight;
+use crate::palette::Palette;
+
+use std::path::Path;
+
+//Writes the display out as an image so tests can check what a ROM drew without a window.
+//Images are at the display's own resolution, one image pixel per Chip8 pixel
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,   //Coloured with the palette
+    Pbm,   //Plain text PBM.  Any lit plane is black
+    Ascii, //One character per pixel: . unlit, # plane 1, + plane 2, @ both
+}
+
+impl ImageFormat {
+    //Picks the format from the file extension.  Anything that isn't .png or .pbm is ASCII art
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("png") => ImageFormat::Png,
+            Some(extension) if extension.eq_ignore_ascii_case("pbm") => ImageFormat::Pbm,
+            _ => ImageFormat::Ascii,
+        }
+    }
+}
+
+pub fn encode(chip8: &ChipEight, format: ImageFormat, palette: &Palette) -> Vec<u8> {
+    let (display, width) = (&chip8.display[..], chip8.display_width());
+    match format {
+        ImageFormat::Png => png(display, width, palette),
+        ImageFormat::Pbm => pbm(display, width),
+        ImageFormat::Ascii => ascii(display, width),
+    }
+}
+
+fn ascii(display: &[u8], width: usize) -> Vec<u8> {
+    const PIXELS: [u8; 4] = [b'.', b'#', b'+', b'@'];
+    let mut text = Vec::new();
+    for row in display.chunks(width) {
+        text.extend(row.iter().map(|&pixel| PIXELS[pixel as usize & 0b11]));
+        text.push(b'\n');
+    }
+    text
+}
+
+fn pbm(display: &[u8], width: usize) -> Vec<u8> {
+    let mut text = format!("P1\n{} {}\n", width, display.len() / width);
+    for row in display.chunks(width) {
+        let pixels: Vec<&str> = row
+            .iter()
+            .map(|&pixel| if pixel != 0 { "1" } else { "0" })
+            .collect();
+        text += &pixels.join(" ");
+        text.push('\n');
+    }
+    text.into_bytes()
+}
+
+//8 bit RGB PNG.  The image data is stored without compression so no deflate implementation is needed
+fn png(display: &[u8], width: usize, palette: &Palette) -> Vec<u8> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+    const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&((display.len() / width) as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); //bit depth, RGB, deflate, adaptive filtering, not interlaced
+
+    //Each row starts with filter type 0, none
+    let mut pixels = Vec::new();
+    for row in display.chunks(width) {
+        pixels.push(0);
+        for &pixel in row {
+            let (r, g, b) = palette.colors[pixel as usize & 0b11];
+            pixels.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    //zlib stream made of stored deflate blocks
+    let mut data = vec![0x78, 0x01];
+    let block_count = pixels.chunks(MAX_STORED_BLOCK).count();
+    for (i, block) in pixels.chunks(MAX_STORED_BLOCK).enumerate() {
+        let last = i + 1 == block_count;
+        data.push(last as u8);
+        data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&pixels).to_be_bytes());
+
+    let mut image = SIGNATURE.to_vec();
+    png_chunk(&mut image, b"IHDR", &header);
+    png_chunk(&mut image, b"IDAT", &data);
+    png_chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+fn png_chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+    let crc = crc32(&image[start..]);
+    image.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //4x2 with every combination of the two planes
+    const DISPLAY: [u8; 8] = [0, 1, 2, 3, 1, 0, 0, 0];
+
+    #[test]
+    fn ascii_shows_each_plane() {
+        assert_eq!(ascii(&DISPLAY, 4), b".#+@\n#...\n");
+    }
+
+    #[test]
+    fn pbm_lights_any_plane() {
+        assert_eq!(pbm(&DISPLAY, 4), b"P1\n4 2\n0 1 1 1\n1 0 0 0\n");
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn png_chunks_are_well_formed() {
+        let palette = Palette::default();
+        let image = png(&DISPLAY, 4, &palette);
+        assert_eq!(&image[..8], b"\x89PNG\r\n\x1A\n");
+
+        //Walk the chunks checking each CRC, which covers the chunk type and data
+        let mut kinds = Vec::new();
+        let mut rest = &image[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (chunk, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc32(chunk).to_be_bytes(), crc[..4]);
+            kinds.push(chunk[..4].to_vec());
+            if &chunk[..4] == b"IHDR" {
+                assert_eq!(&chunk[4..12], &[0, 0, 0, 4, 0, 0, 0, 2]);
+            }
+            rest = &crc[4..];
+        }
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+    }
+
+    #[test]
+    fn dump_format_follows_the_extension() {
+        assert_eq!(ImageFormat::from_path(Path::new("a.PNG")), ImageFormat::Png);
+        assert_eq!(ImageFormat::from_path(Path::new("a.pbm")), ImageFormat::Pbm);
+        assert_eq!(
+            ImageFormat::from_path(Path::new("a.txt")),
+            ImageFormat::Ascii
+        );
+        assert_eq!(ImageFormat::from_path(Path::new("-")), ImageFormat::Ascii);
+    }
+}
@@ -0,0 +1,51 @@
+use chip_eight_emulator::audio::*;
+use chip_eight_emulator::chip_eight::ChipEight;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+
+//Newtype so the library's Tone can be driven by SDL's audio thread
+struct ToneCallback(Tone);
+
+impl AudioCallback for ToneCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.0.fill(out);
+    }
+}
+
+pub struct SdlAudio {
+    device: AudioDevice<ToneCallback>,
+}
+
+impl SdlAudio {
+    pub fn new(sdl_context: &sdl2::Sdl, settings: AudioSettings) -> Result<Self, String> {
+        let audio_subsystem = sdl_context.audio()?;
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44_100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
+            ToneCallback(Tone::new(settings, spec.freq))
+        })?;
+        device.resume(); //The callback outputs silence until the sound timer starts
+
+        Ok(SdlAudio { device })
+    }
+}
+
+impl AudioBackend for SdlAudio {
+    fn update(&mut self, chip8: &ChipEight) {
+        let mut tone = self.device.lock();
+        tone.0.playing = chip8.sound_playing();
+        tone.0.sync_pattern(chip8);
+    }
+
+    fn toggle_mute(&mut self) {
+        let mut tone = self.device.lock();
+        tone.0.settings.muted = !tone.0.settings.muted;
+    }
+}
@@ -0,0 +1,123 @@
+use chip_eight_emulator::gamepad::GamepadMap;
+
+use sdl2::controller::{Axis, GameController};
+use sdl2::event::Event;
+use sdl2::GameControllerSubsystem;
+use std::collections::HashMap;
+use std::path::Path;
+
+//A connected controller and the keypad keys it is holding.  They are let go when it is unplugged
+struct Gamepad {
+    controller: GameController, //Closed when dropped
+    held: [u8; 16],             //How many of its buttons and sticks hold each key
+    axes: HashMap<Axis, u8>,    //The key each stick or trigger is pushing
+}
+
+impl Gamepad {
+    fn press(&mut self, key: u8) {
+        self.held[key as usize] += 1;
+    }
+
+    //The key stays down while another button or stick on the same controller holds it
+    fn release(&mut self, key: u8) {
+        let held = &mut self.held[key as usize];
+        *held = held.saturating_sub(1);
+    }
+}
+
+//Turns game controller events into keypad presses.  SDL sends an added event for every controller
+//already connected at startup as well as ones plugged in later, so both are handled the same way
+pub struct SdlGamepads {
+    subsystem: GameControllerSubsystem,
+    map: GamepadMap,
+    connected: HashMap<u32, Gamepad>, //By joystick instance id, which the other controller events use
+}
+
+impl SdlGamepads {
+    pub fn new(sdl_context: &sdl2::Sdl, map: GamepadMap, mappings: &Path) -> Result<Self, String> {
+        let subsystem = sdl_context.game_controller()?;
+        if mappings.exists() {
+            if let Err(e) = subsystem.load_mappings(mappings) {
+                eprintln!("Could not load {}: {}", mappings.display(), e);
+            }
+        }
+
+        Ok(SdlGamepads {
+            subsystem,
+            map,
+            connected: HashMap::new(),
+        })
+    }
+
+    //The keypad keys any connected controller is holding
+    pub fn keys(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for gamepad in self.connected.values() {
+            for (key, &count) in keys.iter_mut().zip(gamepad.held.iter()) {
+                *key |= count > 0;
+            }
+        }
+        keys
+    }
+
+    //keypad is false while a movie owns the keypad.  Controllers still connect so they work once it finishes
+    pub fn handle_event(&mut self, event: &Event, keypad: bool) {
+        match *event {
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(which) {
+                Ok(controller) => {
+                    println!("Controller connected: {}", controller.name());
+                    self.connected.insert(
+                        controller.instance_id(),
+                        Gamepad {
+                            controller,
+                            held: [0; 16],
+                            axes: HashMap::new(),
+                        },
+                    );
+                }
+                Err(e) => eprintln!("Could not open controller {}: {}", which, e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                if let Some(gamepad) = self.connected.remove(&which) {
+                    println!("Controller disconnected: {}", gamepad.controller.name());
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } if keypad => {
+                if let (Some(gamepad), Some(key)) = (
+                    self.connected.get_mut(&which),
+                    self.map.button(&button.string()),
+                ) {
+                    gamepad.press(key);
+                }
+            }
+            Event::ControllerButtonUp { which, button, .. } if keypad => {
+                if let (Some(gamepad), Some(key)) = (
+                    self.connected.get_mut(&which),
+                    self.map.button(&button.string()),
+                ) {
+                    gamepad.release(key);
+                }
+            }
+            //Sticks press a key when pushed past the deadzone and let it go when they return or point elsewhere
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } if keypad => {
+                if let Some(gamepad) = self.connected.get_mut(&which) {
+                    let key = self.map.axis(&axis.string(), value);
+                    let previous = gamepad.axes.get(&axis).copied();
+                    if key != previous {
+                        if let Some(previous) = previous {
+                            gamepad.axes.remove(&axis);
+                            gamepad.release(previous);
+                        }
+                        if let Some(key) = key {
+                            gamepad.axes.insert(axis, key);
+                            gamepad.press(key);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::chip_eight::RPL_FLAG_COUNT;
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//Per-ROM data that outlives a session is stored in files named after the ROM hash so renaming a ROM doesn't lose it
+
+//$XDG_DATA_HOME/rusted-chip8, ~/.local/share/rusted-chip8 or %APPDATA%\rusted-chip8
+pub fn data_dir() -> PathBuf {
+    const APP_DIR: &str = "rusted-chip8";
+
+    if let Some(dir) = env::var_os("XDG_DATA_HOME") {
+        return PathBuf::from(dir).join(APP_DIR);
+    }
+    if let Some(dir) = env::var_os("APPDATA") {
+        return PathBuf::from(dir).join(APP_DIR);
+    }
+    if let Some(dir) = env::var_os("HOME") {
+        return PathBuf::from(dir)
+            .join(".local")
+            .join("share")
+            .join(APP_DIR);
+    }
+    PathBuf::from(APP_DIR)
+}
+
+//Keyboard and controller mapping, see keymap.rs and gamepad.rs
+pub fn keymap_path(dir: &Path) -> PathBuf {
+    dir.join("keymap.ini")
+}
+
+//Extra SDL controller mappings in the community gamecontrollerdb.txt format
+pub fn controller_db_path(dir: &Path) -> PathBuf {
+    dir.join("gamecontrollerdb.txt")
+}
+
+pub fn save_state_path(dir: &Path, rom_hash: u64, slot: u8) -> PathBuf {
+    dir.join("states")
+        .join(format!("{:016x}.{}.state", rom_hash, slot))
+}
+
+pub fn load_save_state(dir: &Path, rom_hash: u64, slot: u8) -> io::Result<Vec<u8>> {
+    fs::read(save_state_path(dir, rom_hash, slot))
+}
+
+pub fn write_save_state(dir: &Path, rom_hash: u64, slot: u8, state: &[u8]) -> io::Result<()> {
+    let path = save_state_path(dir, rom_hash, slot);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, state)
+}
+
+pub fn rpl_flags_path(dir: &Path, rom_hash: u64) -> PathBuf {
+    dir.join("flags").join(format!("{:016x}.flags", rom_hash))
+}
+
+//Returns all zero flags if nothing has been saved for this ROM yet
+pub fn load_rpl_flags(dir: &Path, rom_hash: u64) -> io::Result<[u8; RPL_FLAG_COUNT]> {
+    let mut flags = [0; RPL_FLAG_COUNT];
+    match fs::read(rpl_flags_path(dir, rom_hash)) {
+        Ok(data) => {
+            let length = data.len().min(RPL_FLAG_COUNT);
+            flags[..length].copy_from_slice(&data[..length]);
+            Ok(flags)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(flags),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn save_rpl_flags(dir: &Path, rom_hash: u64, flags: &[u8; RPL_FLAG_COUNT]) -> io::Result<()> {
+    let path = rpl_flags_path(dir, rom_hash);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, flags)
+}
@@ -1,171 +1,159 @@
-use crate::chip_eight::*;
+use chip_eight_emulator::chip_eight::*;
+use chip_eight_emulator::keymap::Keymap;
+use chip_eight_emulator::palette::Palette;
+use chip_eight_emulator::phosphor::Phosphor;
 extern crate sdl2;
 
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
+use sdl2::keyboard::{Keycode, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
+use sdl2::render::{Texture, TextureCreator, WindowCanvas};
+use sdl2::video::WindowContext;
 
-pub struct UserInterface {
+const BYTES_PER_TEXEL: usize = 3; //RGB24
+
+pub struct UserInterface<'a> {
     canvas: WindowCanvas,
-    scale: usize,
+    //One texel per Chip8 pixel, sized for high resolution.  Low resolution only uses the top left quarter
+    texture: Texture<'a>,
+    texture_stale: bool, //The palette changed so the texture needs refilling even if the display didn't
+    palette: Palette,
+    keymap: Keymap,
+    held: [u8; 16], //How many keyboard keys are holding each keypad key
+    phosphor: Option<Phosphor>,
 }
 
-impl UserInterface {
-    pub fn new(sdl_context: &sdl2::Sdl, size: usize) -> Self {
-        let video_subsystem = sdl_context.video().unwrap();
+//The window is always sized for the low resolution display.  High resolution pixels are half the size
+pub fn create_canvas(sdl_context: &sdl2::Sdl, size: usize) -> Result<WindowCanvas, String> {
+    let video_subsystem = sdl_context.video()?;
+
+    let window = video_subsystem
+        .window(
+            "Chip8",
+            (DISPLAY_WIDTH * size) as u32,
+            (DISPLAY_HEIGHT * size) as u32,
+        )
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
 
-        let window = video_subsystem
-            .window(
-                "Chip8",
-                (crate::DISPLAY_WIDTH * size) as u32,
-                (crate::DISPLAY_HEIGHT * size) as u32,
+    window.into_canvas().build().map_err(|e| e.to_string())
+}
+
+impl<'a> UserInterface<'a> {
+    pub fn new(
+        canvas: WindowCanvas,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        palette: Palette,
+        keymap: Keymap,
+        phosphor: Option<Phosphor>,
+    ) -> Result<Self, String> {
+        let texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                HIRES_DISPLAY_WIDTH as u32,
+                HIRES_DISPLAY_HEIGHT as u32,
             )
-            .position_centered()
-            .build()
-            .unwrap();
+            .map_err(|e| e.to_string())?;
 
         let mut ui = UserInterface {
-            canvas: window.into_canvas().build().unwrap(),
-            scale: size,
+            canvas,
+            texture,
+            texture_stale: true,
+            palette,
+            keymap,
+            held: [0; 16],
+            phosphor,
         };
 
-        ui.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        let (r, g, b) = ui.palette.colors[0];
+        ui.canvas.set_draw_color(Color::RGB(r, g, b));
         ui.canvas.clear();
         ui.canvas.present();
 
-        ui
+        Ok(ui)
     }
 
-    pub fn key_press(&self, chip8: &mut ChipEight, keycode: Keycode) {
-        match keycode {
-            Keycode::Num1 => {
-                chip8.key[0x1] = true;
-            }
-            Keycode::Num2 => {
-                chip8.key[0x2] = true;
-            }
-            Keycode::Num3 => {
-                chip8.key[0x3] = true;
-            }
-            Keycode::Num4 => {
-                chip8.key[0xC] = true;
-            }
-            Keycode::Q => {
-                chip8.key[0x4] = true;
-            }
-            Keycode::W => {
-                chip8.key[0x5] = true;
-            }
-            Keycode::E => {
-                chip8.key[0x6] = true;
-            }
-            Keycode::R => {
-                chip8.key[0xD] = true;
-            }
-            Keycode::A => {
-                chip8.key[0x7] = true;
-            }
-            Keycode::S => {
-                chip8.key[0x8] = true;
-            }
-            Keycode::D => {
-                chip8.key[0x9] = true;
-            }
-            Keycode::F => {
-                chip8.key[0xE] = true;
-            }
-            Keycode::Z => {
-                chip8.key[0xA] = true;
-            }
-            Keycode::X => {
-                chip8.key[0x0] = true;
-            }
-            Keycode::C => {
-                chip8.key[0xB] = true;
-            }
-            Keycode::V => {
-                chip8.key[0xF] = true;
-            }
-            _ => {}
+    //The keypad key a keyboard key is bound to.  Scancode bindings are physical positions so they win over keycodes
+    fn keypad_key(&self, keycode: Keycode, scancode: Option<Scancode>) -> Option<usize> {
+        scancode
+            .and_then(|scancode| self.keymap.scancode(scancode.name()))
+            .or_else(|| self.keymap.keycode(&keycode.name()))
+            .map(usize::from)
+    }
+
+    pub fn is_keypad_key(&self, keycode: Keycode, scancode: Option<Scancode>) -> bool {
+        self.keypad_key(keycode, scancode).is_some()
+    }
+
+    //Call once per physical press, not for key repeats
+    pub fn key_press(&mut self, keycode: Keycode, scancode: Option<Scancode>) {
+        if let Some(key) = self.keypad_key(keycode, scancode) {
+            self.held[key] += 1;
         }
     }
 
-    pub fn key_release(&self, chip8: &mut ChipEight, keycode: Keycode) {
-        match keycode {
-            Keycode::Num1 => {
-                chip8.key[0x1] = false;
-            }
-            Keycode::Num2 => {
-                chip8.key[0x2] = false;
-            }
-            Keycode::Num3 => {
-                chip8.key[0x3] = false;
-            }
-            Keycode::Num4 => {
-                chip8.key[0xC] = false;
-            }
-            Keycode::Q => {
-                chip8.key[0x4] = false;
-            }
-            Keycode::W => {
-                chip8.key[0x5] = false;
-            }
-            Keycode::E => {
-                chip8.key[0x6] = false;
-            }
-            Keycode::R => {
-                chip8.key[0xD] = false;
-            }
-            Keycode::A => {
-                chip8.key[0x7] = false;
-            }
-            Keycode::S => {
-                chip8.key[0x8] = false;
-            }
-            Keycode::D => {
-                chip8.key[0x9] = false;
-            }
-            Keycode::F => {
-                chip8.key[0xE] = false;
-            }
-            Keycode::Z => {
-                chip8.key[0xA] = false;
-            }
-            Keycode::X => {
-                chip8.key[0x0] = false;
-            }
-            Keycode::C => {
-                chip8.key[0xB] = false;
-            }
-            Keycode::V => {
-                chip8.key[0xF] = false;
-            }
-            _ => {}
+    //The keypad key stays down while another keyboard key bound to it is held
+    pub fn key_release(&mut self, keycode: Keycode, scancode: Option<Scancode>) {
+        if let Some(key) = self.keypad_key(keycode, scancode) {
+            self.held[key] = self.held[key].saturating_sub(1);
+        }
+    }
+
+    //The keypad keys held on the keyboard
+    pub fn keys(&self) -> [bool; 16] {
+        self.held.map(|count| count > 0)
+    }
+
+    pub fn palette(&self) -> Palette {
+        self.palette
+    }
+
+    //Takes effect on the next render
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.texture_stale = true;
+    }
+
+    //Lets the phosphor filter fade pixels that went dark.  Call once per 60 Hz frame
+    pub fn end_frame(&mut self, chip8: &ChipEight) {
+        if let Some(phosphor) = self.phosphor.as_mut() {
+            phosphor.end_frame(&chip8.display);
         }
     }
 
-    pub fn render(&mut self, chip8: &ChipEight) {
-        self.canvas.set_draw_color(Color::RGB(255, 0, 0));
-        self.canvas.clear();
-        for i in 0..crate::DISPLAY_SIZE {
-            let pixel = chip8.display[i];
-            let x = i % crate::DISPLAY_WIDTH * self.scale; //get x position of pixel
-            let y = i / crate::DISPLAY_WIDTH * self.scale; //get y position of pixel
-
-            self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-            if pixel == 1 {
-                self.canvas.set_draw_color(Color::RGB(255, 255, 255));
-            }
-
-            let _ = self.canvas.fill_rect(Rect::new(
-                x as i32,
-                y as i32,
-                self.scale as u32,
-                self.scale as u32,
-            )); //Draw the pixel as a square
+    //Presents a frame.  Call once per 60 Hz frame with the core's display dirty flag.  The texture is only
+    //refilled when something changed, or every frame while the phosphor filter is fading pixels
+    pub fn render(&mut self, chip8: &ChipEight, display_changed: bool) -> Result<(), String> {
+        let width = chip8.display_width();
+        let height = chip8.display_height();
+        //Stays stale until an update succeeds, so a failed one is retried next frame
+        self.texture_stale |= display_changed;
+        if self.texture_stale || self.phosphor.is_some() {
+            let palette = &self.palette;
+            let phosphor = &self.phosphor;
+            self.texture
+                .with_lock(None, |texels: &mut [u8], pitch: usize| {
+                    for (i, &pixel) in chip8.display.iter().enumerate() {
+                        let offset = (i / width) * pitch + (i % width) * BYTES_PER_TEXEL;
+                        //Colors for each combination of the two XO-CHIP bitplanes: neither, plane 1, plane 2, both
+                        let (r, g, b) = match phosphor {
+                            Some(phosphor) => phosphor.color(i, pixel, palette),
+                            None => palette.colors[pixel as usize & 0b11],
+                        };
+                        texels[offset..offset + BYTES_PER_TEXEL].copy_from_slice(&[r, g, b]);
+                    }
+                })?;
+            self.texture_stale = false;
         }
 
+        //Stretch the part of the texture the current resolution uses over the whole window
+        self.canvas.copy(
+            &self.texture,
+            Rect::new(0, 0, width as u32, height as u32),
+            None,
+        )?;
         self.canvas.present(); //display changes in window
+        Ok(())
     }
 }